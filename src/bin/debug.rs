@@ -0,0 +1,464 @@
+use std::collections::HashMap;
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, OpenOptions, Permissions};
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process;
+use fuse::{FileAttr, FileType};
+use time::Timespec;
+use risos_fs::bitmap::Bitmap;
+use risos_fs::image::Image;
+use risos_fs::persistence::{Disk, Inode, ranges};
+
+/// Quantidade de bytes lidos de cada vez ao copiar o conteúdo de um arquivo
+const CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Inspeciona uma imagem do RisosFS desmontada, sem gravar nada nela: a imagem é aberta somente para leitura e as
+/// transações que ficaram no journal são refeitas só em memória.
+fn main() {
+    let program = env::args_os().next().unwrap_or_default().to_string_lossy().into_owned();
+    let usage = format!(
+        "Usage: {} <IMAGE> <COMMAND>\n\n\
+         Commands:\n  \
+         superblock              geometria e layout da imagem\n  \
+         inode <INO|PATH>        atributos, references e blocos de um Inode\n  \
+         ls <PATH>               entradas de um diretório\n  \
+         cat <PATH>              conteúdo de um arquivo\n  \
+         map                     mapas de alocação dos Inodes e dos blocos de memória\n  \
+         export <PATH> <DEST>    copia um arquivo da imagem para DEST",
+        program
+    );
+
+    // Os caminhos dentro da imagem podem ter qualquer byte, então os argumentos não precisam ser UTF-8 válido
+    let args: Vec<OsString> = env::args_os().skip(1).collect();
+    if args.len() < 2 {
+        exit_with_usage(&usage);
+    }
+
+    let image_path = Path::new(&args[0]);
+    let image = match Image::open(image_path, true) {
+        Ok(image) => image,
+        Err(e) => {
+            println!("Erro ao abrir a imagem! {}", e);
+            process::exit(1);
+        }
+    };
+
+    // O superbloco e os mapas de bits são lidos direto da imagem, então continuam disponíveis mesmo que o restante
+    // dela não possa ser carregado
+    let disk = Disk::load(image_path, true);
+
+    let result = match (args[1].to_str(), &args[2..], &disk) {
+        (Some("superblock"), [], _) => {
+            print!("{}", image.super_block);
+            Ok(())
+        },
+        (Some("map"), [], Ok(disk)) => {
+            show_map(disk.bitmap(true), disk.bitmap(false), Some(disk));
+            Ok(())
+        },
+        (Some("map"), [], Err(e)) => {
+            println!("Erro ao carregar o disco! {}. Somente os mapas de bits gravados na imagem são exibidos.\n", e);
+            image_bitmaps(&image).map(|(inode_bitmap, block_bitmap)| show_map(&inode_bitmap, &block_bitmap, None))
+        },
+        (_, _, Err(e)) => Err(format!("Erro ao carregar o disco! {}", e)),
+        (Some("inode"), [target], Ok(disk)) => show_inode(disk, &image, Path::new(target)),
+        (Some("ls"), [path], Ok(disk)) => list_directory(disk, Path::new(path)),
+        (Some("cat"), [path], Ok(disk)) => {
+            resolve(disk, Path::new(path)).and_then(|ino| copy_content(disk, ino, &mut io::stdout()))
+        },
+        (Some("export"), [path, destination], Ok(disk)) => export(disk, Path::new(path), &PathBuf::from(destination)),
+        _ => exit_with_usage(&usage)
+    };
+
+    if let Err(message) = result {
+        println!("{}", message);
+        process::exit(1);
+    }
+}
+
+fn exit_with_usage(usage: &str) -> ! {
+    println!("{}", usage);
+    process::exit(2);
+}
+
+/// Número do Inode do caminho `path`, procurado a partir do root da imagem.
+fn resolve(disk: &Disk, path: &Path) -> Result<u64, String> {
+    let mut ino = 1;
+    let names = path.as_os_str().as_bytes().split(|&byte| byte == b'/');
+
+    for name in names.filter(|name| !name.is_empty() && *name != b".") {
+        let inode = get_inode(disk, ino)?;
+        if inode.attributes.kind != FileType::Directory {
+            return Err(format!("{}: não é um diretório!", path.display()));
+        }
+
+        ino = match name {
+            b".." => inode.parent,
+            _ => disk.find_directory_entry(ino, OsStr::from_bytes(name))
+                .ok_or_else(|| format!("{}: {} não encontrado!", path.display(), String::from_utf8_lossy(name)))?
+                .ino
+        };
+    }
+
+    Ok(ino)
+}
+
+/// Inode `ino`, ou a mensagem de erro se ele estiver livre ou com o registro corrompido.
+fn get_inode(disk: &Disk, ino: u64) -> Result<&Inode, String> {
+    match disk.get_inode(ino) {
+        Some(inode) => Ok(inode),
+        None if disk.is_inode_corrupted(ino) => Err(format!("Inode {}: o checksum do registro não confere!", ino)),
+        None => Err(format!("Inode {} não encontrado!", ino))
+    }
+}
+
+/// Caminho do diretório `ino`, montado a partir dos diretórios pai. Retorna `None` se algum diretório no caminho
+/// não estiver na entrada do seu pai, ou se os pais formarem um ciclo.
+fn path_of(disk: &Disk, mut ino: u64) -> Option<String> {
+    let mut names: Vec<String> = Vec::new();
+
+    while ino != 1 {
+        if names.len() > disk.total_inodes() {
+            return None;
+        }
+
+        let parent = disk.get_inode(ino)?.parent;
+        let entry = disk.read_directory(parent)?.into_iter().find(|entry| entry.ino == ino)?;
+        names.push(String::from_utf8_lossy(&entry.name).into_owned());
+        ino = parent;
+    }
+
+    names.reverse();
+    Some(format!("/{}", names.join("/")))
+}
+
+/// Caminhos de todas as entradas de diretório que apontam para o Inode `ino`, encontradas percorrendo todos os
+/// diretórios da imagem.
+fn names_of(disk: &Disk, ino: u64) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+
+    for directory in 1..=disk.total_inodes() as u64 {
+        let entries = match disk.read_directory(directory) {
+            Some(entries) => entries,
+            None => continue
+        };
+
+        let directory_path = path_of(disk, directory).unwrap_or_else(|| format!("<Inode {}>", directory));
+        for entry in entries.into_iter().filter(|entry| entry.ino == ino) {
+            let name = String::from_utf8_lossy(&entry.name);
+            names.push(format!("{}/{}", directory_path.trim_end_matches('/'), name));
+        }
+    }
+
+    names.sort();
+    names
+}
+
+fn show_inode(disk: &Disk, image: &Image, target: &Path) -> Result<(), String> {
+    let ino = match target.to_str().map(str::parse::<u64>) {
+        Some(Ok(ino)) if ino >= 1 && ino <= disk.total_inodes() as u64 => ino,
+        Some(Ok(ino)) => return Err(format!("Inode {} fora da tabela de Inodes (1-{})!", ino, disk.total_inodes())),
+        _ => resolve(disk, target)?
+    };
+
+    let state = if !disk.bitmap(true).is_set(ino as usize - 1) { " (livre no mapa de Inodes)" } else { "" };
+
+    // Um registro corrompido não é carregado, então o que estiver gravado nele é lido sem conferir o checksum
+    if disk.is_inode_corrupted(ino) {
+        println!("Inode {}: o checksum do registro não confere!", ino);
+        let inode = image.read_inode_unchecked(ino as usize - 1)
+            .map_err(|e| format!("Erro ao ler o registro do Inode {}! {}", ino, e))?
+            .ok_or_else(|| format!("O registro do Inode {} está vazio!", ino))?;
+
+        println!("Conteúdo do registro, que pode estar incorreto:");
+        show_attributes(disk, ino, &inode);
+        return Ok(());
+    }
+
+    let inode = get_inode(disk, ino)?;
+    println!("Inode {}{}", ino, state);
+    show_attributes(disk, ino, inode);
+
+    let file_map = disk.file_map(ino)
+        .map_err(|e| format!("Erro ao ler os blocos de ponteiros do Inode {}! {}", ino, io::Error::from_raw_os_error(e)))?;
+
+    println!("  blocos de conteúdo (lógico -> bloco de memória):");
+    for (logical, block_index, length) in runs(&file_map.data_blocks) {
+        match length {
+            1 => println!("    {} -> {}", logical, block_index),
+            _ => println!("    {}-{} -> {}-{}", logical, logical + length - 1, block_index, block_index + length - 1)
+        }
+    }
+
+    if !file_map.pointer_blocks.is_empty() {
+        let pointer_blocks: Vec<String> = file_map.pointer_blocks.iter().map(|block_index| block_index.to_string()).collect();
+        println!("  blocos de ponteiros: {}", pointer_blocks.join(", "));
+    }
+
+    Ok(())
+}
+
+/// Exibe os atributos, os nomes e as references do Inode `ino`.
+fn show_attributes(disk: &Disk, ino: u64, inode: &Inode) {
+    let attributes = &inode.attributes;
+    let parent_path = path_of(disk, inode.parent).unwrap_or_else(|| String::from("?"));
+
+    println!("  ino:        {}", attributes.ino);
+    println!("  kind:       {:?}", attributes.kind);
+    println!("  perm:       {:o} ({})", attributes.perm, mode_string(attributes));
+    println!("  nlink:      {}", attributes.nlink);
+    println!("  uid:        {}", attributes.uid);
+    println!("  gid:        {}", attributes.gid);
+    println!("  size:       {}", attributes.size);
+    println!("  blocks:     {}", attributes.blocks);
+    println!("  rdev:       {}", attributes.rdev);
+    println!("  flags:      {}", attributes.flags);
+    println!("  atime:      {}", timestamp(attributes.atime));
+    println!("  mtime:      {}", timestamp(attributes.mtime));
+    println!("  ctime:      {}", timestamp(attributes.ctime));
+    println!("  crtime:     {}", timestamp(attributes.crtime));
+    // Somente o parent de um diretório é mantido; o de um arquivo é o diretório em que ele foi criado, que pode não
+    // ter mais nenhuma entrada para ele depois de um rename ou de um link
+    match attributes.kind {
+        FileType::Directory => println!("  parent:     {} ({})", inode.parent, parent_path),
+        _ => println!("  criado em:  {} ({})", inode.parent, parent_path)
+    }
+
+    // O nome não fica no Inode, então são listadas todas as entradas de diretório que apontam para ele
+    if ino != 1 {
+        let names = names_of(disk, ino);
+        println!("  nomes:      {}", if names.is_empty() { String::from("-") } else { names.join(", ") });
+    }
+
+    let references: Vec<(usize, usize)> = inode.references.iter().enumerate()
+        .filter_map(|(index, reference)| reference.map(|block_index| (index, block_index)))
+        .collect();
+
+    println!("  references:");
+    for (index, block_index, length) in runs(&references) {
+        match length {
+            1 => println!("    [{}] = {}", index, block_index),
+            _ => println!("    [{}-{}] = {}-{}", index, index + length - 1, block_index, block_index + length - 1)
+        }
+    }
+}
+
+/// Agrupa os pares (posição, bloco de memória) em sequências em que os dois avançam juntos, no formato
+/// (primeira posição, primeiro bloco de memória, quantidade).
+fn runs(blocks: &[(usize, usize)]) -> Vec<(usize, usize, usize)> {
+    let mut runs: Vec<(usize, usize, usize)> = Vec::new();
+
+    for (logical, block_index) in blocks.iter().copied() {
+        match runs.last_mut() {
+            Some((first_logical, first_block, length))
+                if *first_logical + *length == logical && *first_block + *length == block_index => *length += 1,
+            _ => runs.push((logical, block_index, 1))
+        }
+    }
+
+    runs
+}
+
+fn timestamp(time: Timespec) -> String {
+    format!("{}.{:09}", time.sec, time.nsec)
+}
+
+/// Tipo e permissões no formato do `ls -l`.
+fn mode_string(attributes: &FileAttr) -> String {
+    let kind = match attributes.kind {
+        FileType::Directory => 'd',
+        FileType::Symlink => 'l',
+        FileType::NamedPipe => 'p',
+        FileType::CharDevice => 'c',
+        FileType::BlockDevice => 'b',
+        FileType::Socket => 's',
+        FileType::RegularFile => '-'
+    };
+
+    let permissions: String = (0..9).rev()
+        .map(|bit| if attributes.perm & (1 << bit) != 0 { b"xwr"[bit % 3] as char } else { '-' })
+        .collect();
+
+    format!("{}{}", kind, permissions)
+}
+
+fn list_directory(disk: &Disk, path: &Path) -> Result<(), String> {
+    let ino = resolve(disk, path)?;
+    get_inode(disk, ino)?;
+    let mut entries = disk.read_directory(ino).ok_or_else(|| format!("{}: não é um diretório!", path.display()))?;
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+
+    for entry in entries {
+        let name = String::from_utf8_lossy(&entry.name);
+        match disk.get_inode(entry.ino) {
+            Some(inode) => {
+                let attributes = &inode.attributes;
+                println!(
+                    "{:>8} {} {:>3} {:>5} {:>5} {:>12} {}",
+                    entry.ino, mode_string(attributes), attributes.nlink, attributes.uid, attributes.gid,
+                    attributes.size, name
+                );
+            },
+            None => println!("{:>8} {:<10} {:>3} {:>5} {:>5} {:>12} {}", entry.ino, "?", "?", "?", "?", "?", name)
+        }
+    }
+
+    Ok(())
+}
+
+/// Copia o conteúdo do arquivo `ino` para `output`, um trecho de cada vez.
+fn copy_content(disk: &Disk, ino: u64, output: &mut dyn Write) -> Result<(), String> {
+    let inode = get_inode(disk, ino)?;
+    if inode.attributes.kind == FileType::Directory {
+        return Err(format!("Inode {} é um diretório!", ino));
+    }
+
+    let mut offset = 0;
+    while offset < inode.attributes.size {
+        let data = disk.read_content(ino, offset, CHUNK_SIZE).map_err(|e| {
+            format!("Erro ao ler o conteúdo do Inode {}! {}", ino, io::Error::from_raw_os_error(e))
+        })?;
+
+        if data.is_empty() {
+            break;
+        }
+
+        output.write_all(&data).map_err(|e| format!("Erro ao gravar o conteúdo! {}", e))?;
+        offset += data.len() as u64;
+    }
+
+    output.flush().map_err(|e| format!("Erro ao gravar o conteúdo! {}", e))
+}
+
+/// Copia o arquivo `path` da imagem para `destination`, que não pode existir, com as mesmas permissões.
+fn export(disk: &Disk, path: &Path, destination: &Path) -> Result<(), String> {
+    let ino = resolve(disk, path)?;
+    let perm = disk.get_inode(ino).map(|inode| inode.attributes.perm).unwrap_or(0o644);
+
+    let mut file = OpenOptions::new().write(true).create_new(true).open(destination)
+        .map_err(|e| format!("Erro ao criar {}! {}", destination.display(), e))?;
+
+    if let Err(message) = copy_content(disk, ino, &mut file) {
+        drop(file);
+        let _ = fs::remove_file(destination);
+        return Err(message);
+    }
+
+    fs::set_permissions(destination, Permissions::from_mode(u32::from(perm)))
+        .map_err(|e| format!("Erro ao alterar as permissões de {}! {}", destination.display(), e))?;
+
+    println!("{} copiado para {}", path.display(), destination.display());
+    Ok(())
+}
+
+/// Mapas de bits dos Inodes e dos blocos de memória como estão gravados na imagem, sem as transações do journal.
+fn image_bitmaps(image: &Image) -> Result<(Bitmap, Bitmap), String> {
+    let super_block = &image.super_block;
+    let read = |inodes: bool, len: u64| image.read_bitmap(inodes)
+        .map(|bytes| Bitmap::from_bytes(&bytes, len as usize))
+        .map_err(|e| format!("Erro ao ler os mapas de bits! {}", e));
+
+    Ok((read(true, super_block.inode_count)?, read(false, super_block.block_count)?))
+}
+
+/// Exibe os Inodes e os blocos de memória ocupados, junto com os Inodes donos de cada sequência de blocos. Blocos
+/// ocupados sem dono e blocos em uso, mas livres no mapa, também aparecem. Sem o disco carregado, somente os mapas são
+/// exibidos, sem os donos dos blocos.
+fn show_map(inode_bitmap: &Bitmap, block_bitmap: &Bitmap, disk: Option<&Disk>) {
+    let used_inodes: Vec<usize> = (0..inode_bitmap.capacity())
+        .filter(|index| inode_bitmap.is_set(*index))
+        .map(|index| index + 1)
+        .collect();
+
+    println!("Inodes: {} de {} ocupados", used_inodes.len(), inode_bitmap.capacity());
+    if !used_inodes.is_empty() {
+        println!("  {}", ranges(&used_inodes));
+    }
+
+    // Donos de cada bloco de memória, com a indicação de se o bloco é de ponteiros
+    let mut owners: HashMap<usize, Vec<(u64, bool)>> = HashMap::new();
+    if let Some(disk) = disk {
+        show_map_mismatches(disk, inode_bitmap, &mut owners);
+    }
+
+    println!(
+        "Blocos de memória: {} de {} ocupados",
+        block_bitmap.capacity() - block_bitmap.free(), block_bitmap.capacity()
+    );
+
+    let mut current: Option<(usize, String)> = None;
+    for block_index in 0..=block_bitmap.capacity() {
+        let description = if block_index == block_bitmap.capacity() {
+            None
+        } else if disk.is_none() {
+            Some(String::from("ocupado")).filter(|_| block_bitmap.is_set(block_index))
+        } else {
+            describe_block(block_bitmap.is_set(block_index), owners.get(&block_index))
+        };
+
+        if current.as_ref().map(|(_, current)| current) == description.as_ref() {
+            continue;
+        }
+
+        if let Some((first, current)) = current.take() {
+            let blocks = if first + 1 == block_index { first.to_string() } else { format!("{}-{}", first, block_index - 1) };
+            println!("  {:<24} {}", blocks, current);
+        }
+
+        current = description.map(|description| (block_index, description));
+    }
+}
+
+/// Exibe os Inodes em que o mapa não confere com a tabela de Inodes e preenche `owners` com os donos de cada bloco de
+/// memória.
+fn show_map_mismatches(disk: &Disk, inode_bitmap: &Bitmap, owners: &mut HashMap<usize, Vec<(u64, bool)>>) {
+    let mismatched: Vec<usize> = (1..=disk.total_inodes())
+        .filter(|ino| !disk.is_inode_corrupted(*ino as u64))
+        .filter(|ino| disk.get_inode(*ino as u64).is_some() != inode_bitmap.is_set(ino - 1))
+        .collect();
+    if !mismatched.is_empty() {
+        println!("  o mapa não confere com a tabela de Inodes: {}", ranges(&mismatched));
+    }
+
+    let corrupted: Vec<usize> = (1..=disk.total_inodes()).filter(|ino| disk.is_inode_corrupted(*ino as u64)).collect();
+    if !corrupted.is_empty() {
+        println!("  registros com checksum que não confere: {}", ranges(&corrupted));
+    }
+
+    for ino in 1..=disk.total_inodes() as u64 {
+        if disk.get_inode(ino).is_none() {
+            continue;
+        }
+
+        match disk.file_map(ino) {
+            Ok(file_map) => {
+                for (_, block_index) in file_map.data_blocks {
+                    owners.entry(block_index).or_default().push((ino, false));
+                }
+                for block_index in file_map.pointer_blocks {
+                    owners.entry(block_index).or_default().push((ino, true));
+                }
+            },
+            Err(_) => println!("  Erro ao ler os blocos de ponteiros do Inode {}!", ino)
+        }
+    }
+}
+
+/// Descrição de um bloco de memória no mapa de blocos, ou `None` se ele estiver livre e sem dono.
+fn describe_block(used: bool, owners: Option<&Vec<(u64, bool)>>) -> Option<String> {
+    let owners = match owners {
+        Some(owners) => owners,
+        None if used => return Some(String::from("sem dono")),
+        None => return None
+    };
+
+    let owners: Vec<String> = owners.iter()
+        .map(|(ino, pointers)| format!("Inode {}{}", ino, if *pointers { " (ponteiros)" } else { "" }))
+        .collect();
+
+    Some(format!("{}{}", owners.join(", "), if used { "" } else { " (livre no mapa de blocos)" }))
+}
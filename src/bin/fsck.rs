@@ -0,0 +1,82 @@
+use std::env;
+use std::path::PathBuf;
+use std::process;
+use risos_fs::persistence::Disk;
+
+// Códigos de saída do fsck(8)
+const EXIT_OK: i32 = 0;
+const EXIT_REPAIRED: i32 = 1;
+const EXIT_UNREPAIRED: i32 = 4;
+const EXIT_ERROR: i32 = 8;
+const EXIT_USAGE: i32 = 16;
+
+/// Verifica a consistência de uma imagem do RisosFS desmontada e, com `--repair`, grava as correções.
+fn main() {
+    let program = env::args_os().next().unwrap_or_default().to_string_lossy().into_owned();
+    let usage = format!("Usage: {} [--repair] <IMAGE>", program);
+
+    let mut image_path: Option<PathBuf> = None;
+    let mut repair = false;
+
+    // O caminho da imagem não precisa ser UTF-8 válido
+    for arg in env::args_os().skip(1) {
+        match arg.to_str() {
+            Some("--repair") => repair = true,
+            _ if image_path.is_none() => image_path = Some(PathBuf::from(arg)),
+            _ => {
+                println!("{}", usage);
+                process::exit(EXIT_USAGE);
+            }
+        }
+    }
+
+    let image_path = match image_path {
+        Some(path) => path,
+        None => {
+            println!("{}", usage);
+            process::exit(EXIT_USAGE);
+        }
+    };
+
+    println!("Verificando o disco {}...", image_path.display());
+    // Sem `--repair`, nem as transações do journal são gravadas: as correções são feitas somente em memória para
+    // conferir o disco resultante
+    let mut disk = match Disk::load(&image_path, !repair) {
+        Ok(disk) => disk,
+        Err(e) => {
+            println!("Erro ao carregar o disco! {}", e);
+            process::exit(EXIT_ERROR);
+        }
+    };
+
+    let summary = match disk.check() {
+        Ok(summary) => summary,
+        Err(e) => {
+            println!("O disco não pode ser reparado! {}", e);
+            process::exit(EXIT_UNREPAIRED);
+        }
+    };
+
+    if summary.problems == 0 && summary.unrepaired == 0 {
+        println!("Nenhum problema encontrado");
+        process::exit(EXIT_OK);
+    }
+
+    if !repair {
+        println!("{} problemas encontrados. Use --repair para corrigi-los.", summary.problems);
+        process::exit(EXIT_UNREPAIRED);
+    }
+
+    if let Err(e) = disk.write_to_disk() {
+        println!("Erro ao gravar as correções! {}", e);
+        process::exit(EXIT_ERROR);
+    }
+
+    if summary.unrepaired > 0 {
+        println!("{} problemas encontrados, {} não puderam ser corrigidos", summary.problems, summary.unrepaired);
+        process::exit(EXIT_UNREPAIRED);
+    }
+
+    println!("{} problemas encontrados e corrigidos", summary.problems);
+    process::exit(EXIT_REPAIRED);
+}
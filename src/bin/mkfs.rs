@@ -0,0 +1,106 @@
+use std::env;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+use risos_fs::image::SuperBlock;
+use risos_fs::options::{DEFAULT_DISK_SIZE, DEFAULT_BLOCK_SIZE, default_inode_count, parse_size};
+use risos_fs::persistence::Disk;
+use risos_fs::uuid::Uuid;
+
+/// Cria uma imagem nova do RisosFS, pronta para ser montada com `risos_fs --image <IMAGEM>`, e exibe o seu layout.
+fn main() {
+    let program = env::args_os().next().unwrap_or_default().to_string_lossy().into_owned();
+    let usage = format!(
+        "Usage: {} [--size <BYTES>] [--block-size <BYTES>] [--inodes <COUNT>] [--label <LABEL>] [--uuid <UUID>] \
+         [--force] <IMAGE>",
+        program
+    );
+
+    let mut image_path: Option<PathBuf> = None;
+    let mut disk_size: u64 = DEFAULT_DISK_SIZE;
+    let mut block_size: usize = DEFAULT_BLOCK_SIZE;
+    let mut inode_count: Option<usize> = None;
+    let mut label = String::new();
+    let mut uuid: Option<Uuid> = None;
+    let mut force = false;
+    // O caminho da imagem não precisa ser UTF-8 válido, mas as demais opções sim
+    let mut args = env::args_os().skip(1);
+
+    while let Some(arg) = args.next() {
+        match arg.to_str() {
+            Some(option @ ("--size" | "--block-size" | "--inodes")) => {
+                let value = match args.next().as_deref().and_then(OsStr::to_str).and_then(parse_size) {
+                    Some(value) => value,
+                    None => exit_with_usage(&usage)
+                };
+
+                match option {
+                    "--size" => disk_size = value,
+                    "--block-size" => block_size = value as usize,
+                    _ => inode_count = Some(value as usize)
+                }
+            },
+            Some("--label") => match args.next().and_then(|text| text.into_string().ok()) {
+                Some(text) => label = text,
+                None => exit_with_usage(&usage)
+            },
+            Some("--uuid") => match args.next().as_deref().and_then(OsStr::to_str).and_then(|text| text.parse().ok()) {
+                Some(value) => uuid = Some(value),
+                None => exit_with_usage(&usage)
+            },
+            // Permite sobrescrever uma imagem que já existe
+            Some("--force") => force = true,
+            _ if image_path.is_none() => image_path = Some(PathBuf::from(arg)),
+            _ => exit_with_usage(&usage)
+        }
+    }
+
+    let image_path = match image_path {
+        Some(path) => path,
+        None => exit_with_usage(&usage)
+    };
+
+    // Uma imagem existente só é sobrescrita com `--force`, e nunca algo que não seja um arquivo comum
+    if let Ok(metadata) = fs::metadata(&image_path) {
+        if !metadata.is_file() {
+            println!("{} não é um arquivo comum!", image_path.display());
+            process::exit(1);
+        }
+
+        if !force {
+            println!("A imagem {} já existe! Use --force para sobrescrevê-la.", image_path.display());
+            process::exit(1);
+        }
+    }
+
+    let inode_count = inode_count.unwrap_or_else(|| default_inode_count(disk_size));
+    let header = SuperBlock::format(disk_size, block_size, inode_count).and_then(|mut header| {
+        header.set_label(&label)?;
+        if let Some(uuid) = uuid {
+            header.uuid = uuid;
+        }
+        Ok(header)
+    });
+
+    let header = match header {
+        Ok(header) => header,
+        Err(e) => {
+            println!("Opções inválidas! {}", e);
+            process::exit(1);
+        }
+    };
+
+    println!("Formatando o disco {}...", image_path.display());
+    if let Err(e) = Disk::format(&image_path, header) {
+        println!("Erro ao formatar o disco! {}", e);
+        process::exit(1);
+    }
+
+    print!("{}", header);
+}
+
+fn exit_with_usage(usage: &str) -> ! {
+    println!("{}", usage);
+    process::exit(2);
+}
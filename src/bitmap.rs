@@ -0,0 +1,189 @@
+use std::collections::BTreeSet;
+use std::mem;
+
+/// Mapa de bits de alocação: o bit `index` ligado indica que a posição `index` (um Inode da tabela de Inodes ou um
+/// bloco de memória) está ocupada. A quantidade de posições livres é mantida junto, para não precisar percorrer o mapa.
+pub struct Bitmap {
+    words: Vec<u64>,
+    len: usize,
+    free: usize,
+    /// Palavra a partir da qual a próxima posição livre é procurada
+    next: usize,
+    /// Palavras alteradas desde a última vez que o mapa foi gravado na imagem
+    dirty_words: BTreeSet<usize>
+}
+
+impl Bitmap {
+    /// Cria um mapa com `len` posições, todas livres.
+    pub fn new(len: usize) -> Bitmap {
+        Bitmap {
+            words: vec![0; len.div_ceil(64)],
+            len,
+            free: len,
+            next: 0,
+            dirty_words: BTreeSet::new()
+        }
+    }
+
+    /// Reconstrói um mapa com `len` posições a partir das suas palavras gravadas em little-endian.
+    pub fn from_bytes(bytes: &[u8], len: usize) -> Bitmap {
+        let mut bitmap = Bitmap::new(len);
+
+        for (word, chunk) in bitmap.words.iter_mut().zip(bytes.chunks(8)) {
+            let mut word_bytes = [0u8; 8];
+            word_bytes[..chunk.len()].copy_from_slice(chunk);
+            *word = u64::from_le_bytes(word_bytes);
+        }
+
+        // Bits além de `len` na última palavra são ignorados
+        if !len.is_multiple_of(64) {
+            if let Some(last) = bitmap.words.last_mut() {
+                *last &= (1 << (len % 64)) - 1;
+            }
+        }
+
+        let used: usize = bitmap.words.iter().map(|word| word.count_ones() as usize).sum();
+        bitmap.free = len - used;
+        bitmap
+    }
+
+    /// Quantidade de posições do mapa.
+    pub fn capacity(&self) -> usize {
+        self.len
+    }
+
+    /// Quantidade de posições livres.
+    pub fn free(&self) -> usize {
+        self.free
+    }
+
+    /// Indica se alguma palavra foi alterada desde a última chamada de `take_dirty_words`.
+    pub fn is_dirty(&self) -> bool {
+        !self.dirty_words.is_empty()
+    }
+
+    /// Quantidade de palavras alteradas desde a última chamada de `take_dirty_words`.
+    pub fn dirty_word_count(&self) -> usize {
+        self.dirty_words.len()
+    }
+
+    /// Retorna as palavras alteradas desde a última chamada, junto com os seus valores atuais.
+    pub fn take_dirty_words(&mut self) -> Vec<(usize, u64)> {
+        let dirty_words = mem::take(&mut self.dirty_words);
+        dirty_words.into_iter().map(|index| (index, self.words[index])).collect()
+    }
+
+    pub fn is_set(&self, index: usize) -> bool {
+        self.words[index / 64] & (1 << (index % 64)) != 0
+    }
+
+    /// Marca a posição `index` como ocupada.
+    pub fn set(&mut self, index: usize) {
+        if !self.is_set(index) {
+            self.words[index / 64] |= 1 << (index % 64);
+            self.free -= 1;
+            self.dirty_words.insert(index / 64);
+        }
+    }
+
+    /// Marca a posição `index` como livre. Como ela fica antes da busca, a próxima alocação volta a considerá-la.
+    pub fn clear(&mut self, index: usize) {
+        if self.is_set(index) {
+            self.words[index / 64] &= !(1 << (index % 64));
+            self.free += 1;
+            self.next = self.next.min(index / 64);
+            self.dirty_words.insert(index / 64);
+        }
+    }
+
+    /// Retorna a primeira posição livre a partir da última busca, sem marcá-la como ocupada. Palavras totalmente
+    /// ocupadas são puladas de uma vez e a busca recomeça de onde parou, então o custo é O(1) amortizado.
+    pub fn find_free(&mut self) -> Option<usize> {
+        if self.free == 0 {
+            return None;
+        }
+
+        while self.next < self.words.len() {
+            let word = self.words[self.next];
+            if word != u64::MAX {
+                let index = self.next * 64 + (!word).trailing_zeros() as usize;
+                // Os bits da última palavra além de `len` ficam sempre desligados
+                return if index < self.len { Some(index) } else { None };
+            }
+
+            self.next += 1;
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allocates_every_slot_including_the_last() {
+        let mut bitmap = Bitmap::new(70);
+
+        for expected in 0..70 {
+            let index = bitmap.find_free().unwrap();
+            assert_eq!(index, expected);
+            bitmap.set(index);
+        }
+
+        assert_eq!(bitmap.free(), 0);
+        assert_eq!(bitmap.find_free(), None);
+
+        // A última posição volta a ser encontrada depois de liberada
+        bitmap.clear(69);
+        assert_eq!(bitmap.find_free(), Some(69));
+    }
+
+    #[test]
+    fn cleared_slot_is_reused_before_later_ones() {
+        let mut bitmap = Bitmap::new(200);
+        for index in 0..130 {
+            bitmap.set(index);
+        }
+
+        assert_eq!(bitmap.find_free(), Some(130));
+        bitmap.clear(5);
+        assert_eq!(bitmap.find_free(), Some(5));
+    }
+
+    #[test]
+    fn from_bytes_ignores_bits_past_len() {
+        let mut bytes = vec![0u8; 16];
+        bytes[..8].copy_from_slice(&u64::MAX.to_le_bytes());
+        bytes[8..].copy_from_slice(&u64::MAX.to_le_bytes());
+
+        let mut bitmap = Bitmap::from_bytes(&bytes, 70);
+        assert_eq!(bitmap.capacity(), 70);
+        assert_eq!(bitmap.free(), 0);
+        assert_eq!(bitmap.find_free(), None);
+
+        bitmap.clear(66);
+        assert_eq!(bitmap.free(), 1);
+        assert_eq!(bitmap.find_free(), Some(66));
+    }
+
+    #[test]
+    fn from_bytes_round_trips_dirty_words() {
+        let mut bitmap = Bitmap::new(100);
+        bitmap.set(3);
+        bitmap.set(64);
+        bitmap.set(99);
+
+        let mut bytes = vec![0u8; 16];
+        for (index, word) in bitmap.take_dirty_words() {
+            bytes[index * 8..index * 8 + 8].copy_from_slice(&word.to_le_bytes());
+        }
+        assert!(!bitmap.is_dirty());
+
+        let loaded = Bitmap::from_bytes(&bytes, 100);
+        assert_eq!(loaded.free(), 97);
+        assert!(loaded.is_set(3) && loaded.is_set(64) && loaded.is_set(99));
+        assert!(!loaded.is_set(4));
+    }
+}
@@ -0,0 +1,288 @@
+use std::ffi::{CString, OsStr};
+use std::io;
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::process::Command;
+use std::ptr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+use fuse::{Filesystem, Request, ReplyCreate, ReplyEmpty, ReplyAttr, ReplyEntry, ReplyOpen, ReplyData, ReplyDirectory, ReplyWrite, ReplyStatfs};
+use libc::{c_int, sigset_t, SIG_BLOCK, SIGINT, SIGTERM, SIGHUP};
+use time::Timespec;
+use crate::RisosFS;
+
+/// Sinais que desmontam o FS e salvam o disco antes de encerrar o processo
+const SHUTDOWN_SIGNALS: [c_int; 3] = [SIGINT, SIGTERM, SIGHUP];
+/// Intervalo entre as verificações de sinais recebidos e de desmontagem externa do FS
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// FS compartilhado entre a thread do FUSE, que atende as requisições do kernel, e a thread principal, que faz os
+/// checkpoints periódicos. Cada requisição é atendida com o FS travado, então um checkpoint nunca acontece no meio de
+/// uma operação.
+struct SharedFS(Arc<Mutex<RisosFS>>);
+
+impl SharedFS {
+    fn fs(&self) -> MutexGuard<'_, RisosFS> {
+        // O mutex só fica envenenado se uma operação entrou em pânico, e nesse caso a thread do FUSE já terminou
+        self.0.lock().unwrap()
+    }
+}
+
+/// Monta o FS em `mountpoint` e o mantém montado até que o processo receba um sinal de encerramento ou que o FS seja
+/// desmontado externamente (`fusermount -u`). A cada `checkpoint_interval` as alterações são aplicadas na imagem. Ao
+/// receber um sinal, o disco é salvo antes de desmontar o FS; se ele não puder ser desmontado (algum processo ainda o
+/// usa), o FS continua montado até o próximo sinal.
+pub fn run(fs: RisosFS, mountpoint: &Path, options: &[&OsStr], checkpoint_interval: Option<Duration>) -> io::Result<()> {
+    let signals = wait_for_signals()?;
+    let fs = Arc::new(Mutex::new(fs));
+    let session = unsafe { fuse::spawn_mount(SharedFS(Arc::clone(&fs)), &mountpoint, options)? };
+
+    println!("RisosFS started!");
+    let mut last_checkpoint = Instant::now();
+
+    loop {
+        match signals.recv_timeout(POLL_INTERVAL) {
+            Ok(signal) => {
+                println!("Sinal {} recebido, desmontando o FS...", signal);
+
+                // O disco é salvo antes, então nada se perde mesmo que a desmontagem falhe
+                checkpoint(&fs);
+                match unmount(mountpoint) {
+                    Ok(()) => break,
+                    Err(e) => println!(
+                        "Não foi possível desmontar o FS! {}. Ele continua montado: feche os arquivos abertos nele e \
+                         envie o sinal de novo.", e
+                    )
+                }
+            },
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(RecvTimeoutError::Disconnected) => break
+        }
+
+        // A sessão do FUSE guarda a outra referência ao FS e a descarta quando termina: o FS foi desmontado
+        // externamente ou a thread do FUSE entrou em pânico
+        if Arc::strong_count(&fs) == 1 {
+            println!("FS desmontado");
+            break;
+        }
+
+        if let Some(interval) = checkpoint_interval {
+            if last_checkpoint.elapsed() >= interval {
+                checkpoint(&fs);
+                last_checkpoint = Instant::now();
+            }
+        }
+    }
+
+    // Com o FS já desmontado, espera a thread do FUSE terminar, o que repassa um pânico dela
+    if panic::catch_unwind(AssertUnwindSafe(|| drop(session))).is_err() {
+        println!("A thread do FUSE entrou em pânico!");
+    }
+
+    match Arc::try_unwrap(fs).map(Mutex::into_inner) {
+        // O disco é salvo ao descartar o FS
+        Ok(Ok(fs)) => drop(fs),
+        // A operação interrompida pelo pânico pode ter deixado alterações pela metade em memória, então elas não são
+        // salvas: as transações já gravadas no journal são refeitas na próxima montagem
+        Ok(Err(poisoned)) => {
+            println!("Descartando as alterações que não foram gravadas no journal...");
+            mem::forget(poisoned.into_inner());
+        },
+        Err(_) => println!("A thread do FUSE não terminou! O disco não foi salvo.")
+    }
+
+    Ok(())
+}
+
+/// Aplica na imagem as alterações feitas desde o último checkpoint, se houver alguma.
+fn checkpoint(fs: &Mutex<RisosFS>) {
+    let mut fs = match fs.lock() {
+        Ok(fs) => fs,
+        Err(_) => return
+    };
+
+    if fs.disk.is_clean() {
+        return;
+    }
+
+    match fs.disk.write_to_disk() {
+        Ok(()) => println!("Checkpoint: alterações gravadas na imagem"),
+        Err(e) => println!("Erro no checkpoint! {}", e)
+    }
+}
+
+/// Desmonta o FS em `mountpoint`. A desmontagem feita ao descartar a sessão do FUSE só registra um erro e depois
+/// espera a thread do FUSE para sempre, então ela só é usada depois que esta tiver sucesso.
+fn unmount(mountpoint: &Path) -> io::Result<()> {
+    let path = CString::new(mountpoint.as_os_str().as_bytes())?;
+    if unsafe { libc::umount(path.as_ptr()) } == 0 {
+        return Ok(());
+    }
+
+    let error = io::Error::last_os_error();
+    if error.kind() != io::ErrorKind::PermissionDenied {
+        return Err(error);
+    }
+
+    // Sem ser root, somente o fusermount, que é setuid, consegue desmontar o FS
+    let output = Command::new("fusermount").arg("-u").arg(mountpoint).output()?;
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other(String::from_utf8_lossy(&output.stderr).trim().to_string()))
+    }
+}
+
+/// Bloqueia os sinais de encerramento na thread atual, e portanto nas threads criadas depois dela, e cria uma thread
+/// que os recebe com sigwait e os repassa pelo canal retornado. Deve ser chamada antes de montar o FS, para que um
+/// sinal nunca interrompa a thread do FUSE no meio de uma operação.
+fn wait_for_signals() -> io::Result<Receiver<c_int>> {
+    let set = unsafe {
+        let mut set: sigset_t = mem::zeroed();
+        libc::sigemptyset(&mut set);
+        for signal in SHUTDOWN_SIGNALS.iter() {
+            libc::sigaddset(&mut set, *signal);
+        }
+        set
+    };
+
+    let result = unsafe { libc::pthread_sigmask(SIG_BLOCK, &set, ptr::null_mut()) };
+    if result != 0 {
+        return Err(io::Error::from_raw_os_error(result));
+    }
+
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || loop {
+        let mut signal: c_int = 0;
+        if unsafe { libc::sigwait(&set, &mut signal) } == 0 && sender.send(signal).is_err() {
+            break;
+        }
+    });
+
+    Ok(receiver)
+}
+
+/// Repassa cada requisição para o `RisosFS` compartilhado
+impl Filesystem for SharedFS {
+    fn lookup(&mut self, req: &Request, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        self.fs().lookup(req, parent, name, reply)
+    }
+
+    fn create(&mut self, req: &Request, parent: u64, name: &OsStr, mode: u32, flags: u32, reply: ReplyCreate) {
+        self.fs().create(req, parent, name, mode, flags, reply)
+    }
+
+    fn fsync(&mut self, req: &Request, ino: u64, fh: u64, datasync: bool, reply: ReplyEmpty) {
+        self.fs().fsync(req, ino, fh, datasync, reply)
+    }
+
+    fn fsyncdir(&mut self, req: &Request, ino: u64, fh: u64, datasync: bool, reply: ReplyEmpty) {
+        self.fs().fsyncdir(req, ino, fh, datasync, reply)
+    }
+
+    fn setattr(
+        &mut self,
+        req: &Request,
+        ino: u64,
+        mode: Option<u32>,
+        uid: Option<u32>,
+        gid: Option<u32>,
+        size: Option<u64>,
+        atime: Option<Timespec>,
+        mtime: Option<Timespec>,
+        fh: Option<u64>,
+        crtime: Option<Timespec>,
+        chgtime: Option<Timespec>,
+        bkuptime: Option<Timespec>,
+        flags: Option<u32>,
+        reply: ReplyAttr
+    ) {
+        self.fs().setattr(req, ino, mode, uid, gid, size, atime, mtime, fh, crtime, chgtime, bkuptime, flags, reply)
+    }
+
+    fn getattr(&mut self, req: &Request, ino: u64, reply: ReplyAttr) {
+        self.fs().getattr(req, ino, reply)
+    }
+
+    fn mkdir(&mut self, req: &Request, parent: u64, name: &OsStr, mode: u32, reply: ReplyEntry) {
+        self.fs().mkdir(req, parent, name, mode, reply)
+    }
+
+    fn rmdir(&mut self, req: &Request, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        self.fs().rmdir(req, parent, name, reply)
+    }
+
+    fn open(&mut self, req: &Request, ino: u64, flags: u32, reply: ReplyOpen) {
+        self.fs().open(req, ino, flags, reply)
+    }
+
+    fn flush(&mut self, req: &Request, ino: u64, fh: u64, lock_owner: u64, reply: ReplyEmpty) {
+        self.fs().flush(req, ino, fh, lock_owner, reply)
+    }
+
+    fn release(&mut self, req: &Request, ino: u64, fh: u64, flags: u32, lock_owner: u64, flush: bool, reply: ReplyEmpty) {
+        self.fs().release(req, ino, fh, flags, lock_owner, flush, reply)
+    }
+
+    fn read(&mut self, req: &Request, ino: u64, fh: u64, offset: i64, size: u32, reply: ReplyData) {
+        self.fs().read(req, ino, fh, offset, size, reply)
+    }
+
+    fn opendir(&mut self, req: &Request, ino: u64, flags: u32, reply: ReplyOpen) {
+        self.fs().opendir(req, ino, flags, reply)
+    }
+
+    fn readdir(&mut self, req: &Request, ino: u64, fh: u64, offset: i64, reply: ReplyDirectory) {
+        self.fs().readdir(req, ino, fh, offset, reply)
+    }
+
+    fn write(&mut self, req: &Request, ino: u64, fh: u64, offset: i64, data: &[u8], flags: u32, reply: ReplyWrite) {
+        self.fs().write(req, ino, fh, offset, data, flags, reply)
+    }
+
+    fn unlink(&mut self, req: &Request, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        self.fs().unlink(req, parent, name, reply)
+    }
+
+    fn rename(&mut self, req: &Request, parent: u64, name: &OsStr, newparent: u64, newname: &OsStr, reply: ReplyEmpty) {
+        self.fs().rename(req, parent, name, newparent, newname, reply)
+    }
+
+    #[cfg(target_os = "macos")]
+    fn exchange(
+        &mut self,
+        req: &Request,
+        parent: u64,
+        name: &OsStr,
+        newparent: u64,
+        newname: &OsStr,
+        options: u64,
+        reply: ReplyEmpty
+    ) {
+        self.fs().exchange(req, parent, name, newparent, newname, options, reply)
+    }
+
+    fn link(&mut self, req: &Request, ino: u64, newparent: u64, newname: &OsStr, reply: ReplyEntry) {
+        self.fs().link(req, ino, newparent, newname, reply)
+    }
+
+    fn symlink(&mut self, req: &Request, parent: u64, name: &OsStr, link: &Path, reply: ReplyEntry) {
+        self.fs().symlink(req, parent, name, link, reply)
+    }
+
+    fn readlink(&mut self, req: &Request, ino: u64, reply: ReplyData) {
+        self.fs().readlink(req, ino, reply)
+    }
+
+    fn access(&mut self, req: &Request, ino: u64, mask: u32, reply: ReplyEmpty) {
+        self.fs().access(req, ino, mask, reply)
+    }
+
+    fn statfs(&mut self, req: &Request, ino: u64, reply: ReplyStatfs) {
+        self.fs().statfs(req, ino, reply)
+    }
+}
@@ -0,0 +1,383 @@
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+use serde::{Serialize, Deserialize};
+use bincode::{serialize, deserialize, serialized_size};
+use crate::checksum::crc32c;
+use crate::persistence::Inode;
+use crate::uuid::Uuid;
+
+/// Assinatura gravada no começo de toda imagem do RisosFS
+pub const MAGIC: [u8; 8] = *b"RISOSFS\0";
+/// Versão do formato da imagem
+pub const VERSION: u32 = 5;
+/// Espaço reservado para cada Inode na tabela de Inodes, em bytes
+pub const INODE_RECORD_SIZE: usize = 2048;
+/// Tamanho do checksum gravado no começo de cada registro da tabela de Inodes e na tabela de checksums dos blocos
+const CHECKSUM_SIZE: usize = 4;
+/// Tamanho máximo do rótulo do disco, em bytes
+pub const LABEL_SIZE: usize = 16;
+/// Tamanho máximo do journal, em bytes
+pub const JOURNAL_SIZE: u64 = 64 * 1024 * 1024;
+/// Tamanho mínimo do journal, em bytes
+const MIN_JOURNAL_SIZE: u64 = 1024 * 1024;
+/// Menor tamanho de bloco aceito: um bloco precisa comportar ao menos uma entrada de diretório com o maior nome
+pub const MIN_BLOCK_SIZE: usize = 1024;
+/// Maior tamanho de bloco aceito
+pub const MAX_BLOCK_SIZE: usize = 64 * 1024 * 1024;
+
+/// Cabeçalho gravado no primeiro bloco da imagem. Descreve a geometria do disco e onde começa cada região; todas as
+/// regiões começam em um múltiplo do tamanho do bloco.
+///
+/// ```text
+/// | superbloco | mapa de Inodes | mapa de blocos | checksums dos blocos | tabela de Inodes | journal | blocos de memória |
+/// ```
+///
+/// Os mapas de bits são gravados em palavras de 64 bits little-endian. Cada bloco de memória tem o seu CRC32C na
+/// tabela de checksums, e cada registro da tabela de Inodes começa com o CRC32C do restante do registro; ambos são
+/// conferidos a cada leitura. Posições nunca gravadas ficam zeradas, inclusive o checksum, e são aceitas como estão.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct SuperBlock {
+    pub magic: [u8; 8],
+    pub version: u32,
+    pub uuid: Uuid,
+    /// Rótulo do disco em UTF-8, completado com zeros
+    pub label: [u8; LABEL_SIZE],
+    pub block_size: u64,
+    pub inode_count: u64,
+    pub block_count: u64,
+    pub inode_bitmap_offset: u64,
+    pub block_bitmap_offset: u64,
+    pub checksum_table_offset: u64,
+    pub inode_table_offset: u64,
+    pub journal_offset: u64,
+    pub journal_size: u64,
+    pub data_offset: u64
+}
+
+impl SuperBlock {
+    /// Calcula a posição de cada região para um disco com `inode_count` Inodes, `block_count` blocos de memória de
+    /// `block_size` bytes e um journal de `journal_size` bytes.
+    pub fn new(block_size: usize, inode_count: usize, block_count: usize, journal_size: u64) -> SuperBlock {
+        let block_size = block_size as u64;
+        // Tamanho de uma região arredondado para blocos inteiros
+        let region = |bytes: u64| bytes.div_ceil(block_size) * block_size;
+
+        let inode_bitmap_offset = block_size;
+        let block_bitmap_offset = inode_bitmap_offset + region(bitmap_size(inode_count as u64));
+        let checksum_table_offset = block_bitmap_offset + region(bitmap_size(block_count as u64));
+        let inode_table_offset = checksum_table_offset + region((block_count * CHECKSUM_SIZE) as u64);
+        let journal_offset = inode_table_offset + region((inode_count * INODE_RECORD_SIZE) as u64);
+        // O journal precisa comportar ao menos algumas transações com um bloco inteiro cada
+        let journal_size = region(journal_size.max(4 * block_size));
+        let data_offset = journal_offset + journal_size;
+
+        SuperBlock {
+            magic: MAGIC,
+            version: VERSION,
+            uuid: Uuid::default(),
+            label: [0; LABEL_SIZE],
+            block_size,
+            inode_count: inode_count as u64,
+            block_count: block_count as u64,
+            inode_bitmap_offset,
+            block_bitmap_offset,
+            checksum_table_offset,
+            inode_table_offset,
+            journal_offset,
+            journal_size,
+            data_offset
+        }
+    }
+
+    /// Calcula a geometria de um disco novo que ocupa no máximo `disk_size` bytes, com blocos de `block_size` bytes e
+    /// `inode_count` Inodes. Todo o espaço que sobra depois dos mapas de bits, da tabela de Inodes e do journal vira
+    /// blocos de memória. O journal ocupa 1/16 do disco, entre 1 MiB e `JOURNAL_SIZE`. O disco recebe um UUID
+    /// aleatório e nenhum rótulo.
+    pub fn format(disk_size: u64, block_size: usize, inode_count: usize) -> io::Result<SuperBlock> {
+        if !block_size.is_power_of_two() || !(MIN_BLOCK_SIZE..=MAX_BLOCK_SIZE).contains(&block_size) {
+            return Err(invalid_input(&format!(
+                "o tamanho do bloco deve ser uma potência de 2 entre {} e {} bytes", MIN_BLOCK_SIZE, MAX_BLOCK_SIZE
+            )));
+        }
+
+        if inode_count == 0 {
+            return Err(invalid_input("o disco precisa de ao menos um Inode"));
+        }
+
+        let journal_size = (disk_size / 16).clamp(MIN_JOURNAL_SIZE, JOURNAL_SIZE);
+        let metadata_size = SuperBlock::new(block_size, inode_count, 0, journal_size).image_size();
+        if metadata_size >= disk_size {
+            return Err(invalid_input("tamanho do disco insuficiente para os Inodes e o journal"));
+        }
+
+        // Cada bloco ocupa, além do seu conteúdo, um bit no mapa de blocos e o seu checksum. A estimativa ignora o
+        // arredondamento das regiões para blocos inteiros, então é corrigida para baixo até caber
+        let per_block = 8 * (block_size as u64 + CHECKSUM_SIZE as u64) + 1;
+        let mut block_count = ((disk_size - metadata_size) * 8 / per_block) as usize;
+        while block_count > 0 && SuperBlock::new(block_size, inode_count, block_count, journal_size).image_size() > disk_size {
+            block_count -= 1;
+        }
+
+        if block_count == 0 {
+            return Err(invalid_input("tamanho do disco insuficiente para um bloco de memória"));
+        }
+
+        let mut super_block = SuperBlock::new(block_size, inode_count, block_count, journal_size);
+        super_block.uuid = Uuid::random()?;
+        Ok(super_block)
+    }
+
+    /// Tamanho total da imagem, em bytes.
+    pub fn image_size(&self) -> u64 {
+        self.data_offset + self.block_count * self.block_size
+    }
+
+    /// Rótulo do disco, vazio se o disco não tiver um.
+    pub fn label(&self) -> String {
+        let len = self.label.iter().position(|byte| *byte == 0).unwrap_or(LABEL_SIZE);
+        String::from_utf8_lossy(&self.label[..len]).into_owned()
+    }
+
+    /// Troca o rótulo do disco, que deve ter no máximo `LABEL_SIZE` bytes.
+    pub fn set_label(&mut self, label: &str) -> io::Result<()> {
+        if label.len() > LABEL_SIZE || label.contains('\0') {
+            return Err(invalid_input(&format!("o rótulo deve ter no máximo {} bytes", LABEL_SIZE)));
+        }
+
+        self.label = [0; LABEL_SIZE];
+        self.label[..label.len()].copy_from_slice(label.as_bytes());
+        Ok(())
+    }
+}
+
+/// Layout do disco, uma região por linha, no formato exibido pelas ferramentas de linha de comando.
+impl fmt::Display for SuperBlock {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let region = |f: &mut fmt::Formatter, name: &str, start: u64, end: u64| {
+            writeln!(f, "  {:<22} {:>14} {:>14} ({} blocos)", name, start, end - start, (end - start) / self.block_size)
+        };
+
+        writeln!(f, "UUID:                {}", self.uuid)?;
+        writeln!(f, "Rótulo:              {}", self.label())?;
+        writeln!(f, "Versão do formato:   {}", self.version)?;
+        writeln!(f, "Tamanho da imagem:   {} bytes", self.image_size())?;
+        writeln!(f, "Tamanho do bloco:    {} bytes", self.block_size)?;
+        writeln!(f, "Inodes:              {}", self.inode_count)?;
+        writeln!(f, "Blocos de memória:   {}", self.block_count)?;
+        writeln!(f, "  {:<22} {:>14} {:>14}", "Região", "Início", "Tamanho")?;
+        region(f, "superbloco", 0, self.inode_bitmap_offset)?;
+        region(f, "mapa de Inodes", self.inode_bitmap_offset, self.block_bitmap_offset)?;
+        region(f, "mapa de blocos", self.block_bitmap_offset, self.checksum_table_offset)?;
+        region(f, "checksums dos blocos", self.checksum_table_offset, self.inode_table_offset)?;
+        region(f, "tabela de Inodes", self.inode_table_offset, self.journal_offset)?;
+        region(f, "journal", self.journal_offset, self.data_offset)?;
+        region(f, "blocos de memória", self.data_offset, self.image_size())
+    }
+}
+
+/// Arquivo de imagem com o layout descrito em `SuperBlock`. Cada Inode, bloco de memória ou mapa de bits é lido e
+/// gravado diretamente na sua posição com pread/pwrite, sem carregar a imagem inteira.
+pub struct Image {
+    file: File,
+    pub super_block: SuperBlock
+}
+
+/// Tamanho em bytes de um mapa de bits com `len` posições, arredondado para palavras de 64 bits.
+fn bitmap_size(len: u64) -> u64 {
+    len.div_ceil(64) * 8
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn invalid_input(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+/// Confere `content` com o checksum little-endian `checksum`. Um checksum zerado junto com um conteúdo zerado
+/// corresponde a uma posição que nunca foi gravada.
+fn checksum_matches(checksum: &[u8], content: &[u8]) -> bool {
+    let mut bytes = [0u8; CHECKSUM_SIZE];
+    bytes.copy_from_slice(checksum);
+
+    match u32::from_le_bytes(bytes) {
+        0 if content.iter().all(|byte| *byte == 0) => true,
+        checksum => checksum == crc32c(content)
+    }
+}
+
+impl Image {
+    /// Cria (ou sobrescreve) a imagem em `path` com a geometria de `super_block`. O arquivo é esparso: regiões que
+    /// nunca foram escritas não ocupam espaço no disco do sistema hospedeiro e são lidas como zero.
+    ///
+    /// A imagem é montada em um arquivo temporário ao lado de `path` e só então renomeada, então uma queda durante a
+    /// criação nunca deixa em `path` uma imagem sem cabeçalho ou menor que o indicado nele.
+    pub fn create<P: AsRef<Path>>(path: P, super_block: SuperBlock) -> io::Result<Image> {
+        let path = path.as_ref();
+        let mut temporary_name = path.file_name().unwrap_or_default().to_os_string();
+        temporary_name.push(".tmp");
+        let temporary_path = path.with_file_name(temporary_name);
+
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&temporary_path)?;
+        file.set_len(super_block.image_size())?;
+
+        let image = Image { file, super_block };
+        image.write_super_block()?;
+        image.file.sync_all()?;
+
+        fs::rename(&temporary_path, path)?;
+        // O rename só é persistente depois que o diretório que contém a imagem é sincronizado
+        let directory = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new(".")
+        };
+        File::open(directory)?.sync_all()?;
+
+        Ok(image)
+    }
+
+    /// Abre uma imagem existente, validando a assinatura, a versão e o tamanho do arquivo. Com `read_only`, o arquivo é
+    /// aberto somente para leitura e qualquer gravação falha.
+    pub fn open<P: AsRef<Path>>(path: P, read_only: bool) -> io::Result<Image> {
+        let file = OpenOptions::new().read(true).write(!read_only).open(path)?;
+
+        let mut header = vec![0u8; serialized_size(&SuperBlock::new(1, 0, 0, 0)).unwrap() as usize];
+        file.read_exact_at(&mut header, 0)?;
+        let super_block: SuperBlock = deserialize(&header).map_err(|_| invalid_data("cabeçalho inválido"))?;
+
+        if super_block.magic != MAGIC {
+            return Err(invalid_data("o arquivo não é uma imagem do RisosFS"));
+        }
+
+        if super_block.version != VERSION {
+            return Err(invalid_data("versão da imagem não suportada"));
+        }
+
+        if file.metadata()?.len() < super_block.image_size() {
+            return Err(invalid_data("imagem menor que o tamanho indicado no cabeçalho"));
+        }
+
+        Ok(Image { file, super_block })
+    }
+
+    pub fn write_super_block(&self) -> io::Result<()> {
+        let header = serialize(&self.super_block).map_err(|_| invalid_data("cabeçalho inválido"))?;
+        self.file.write_all_at(&header, 0)
+    }
+
+    /// Lê a tabela de Inodes inteira. Posições nunca gravadas (zeradas) correspondem a `None`, e as posições cujo
+    /// checksum não confere a um erro, sem impedir a leitura das demais. Retorna um erro somente se a tabela não puder
+    /// ser lida.
+    pub fn read_inodes(&self) -> io::Result<Vec<io::Result<Option<Inode>>>> {
+        let mut table = vec![0u8; self.super_block.inode_count as usize * INODE_RECORD_SIZE];
+        self.file.read_exact_at(&mut table, self.super_block.inode_table_offset)?;
+
+        let inodes = table.chunks(INODE_RECORD_SIZE)
+            .enumerate()
+            .map(|(index, record)| {
+                let (checksum, content) = record.split_at(CHECKSUM_SIZE);
+                if !checksum_matches(checksum, content) {
+                    return Err(invalid_data(&format!("checksum do Inode {} não confere", index + 1)));
+                }
+
+                deserialize(content).map_err(|_| invalid_data(&format!("Inode {} corrompido", index + 1)))
+            })
+            .collect();
+
+        Ok(inodes)
+    }
+
+    /// Lê o Inode da posição `index` da tabela de Inodes sem conferir o checksum, para recuperar o conteúdo de um registro
+    /// corrompido. Retorna um erro se o registro não puder ser lido ou desserializado.
+    pub fn read_inode_unchecked(&self, index: usize) -> io::Result<Option<Inode>> {
+        let mut record = vec![0u8; INODE_RECORD_SIZE];
+        self.file.read_exact_at(&mut record, self.super_block.inode_table_offset + (index * INODE_RECORD_SIZE) as u64)?;
+
+        deserialize(&record[CHECKSUM_SIZE..]).map_err(|_| invalid_data(&format!("Inode {} corrompido", index + 1)))
+    }
+
+    /// Grava o Inode da posição `index` da tabela de Inodes (`None` para uma posição livre).
+    pub fn write_inode(&self, index: usize, inode: &Option<Inode>) -> io::Result<()> {
+        let content = serialize(inode).map_err(|_| invalid_data("Inode inválido"))?;
+        if content.len() > INODE_RECORD_SIZE - CHECKSUM_SIZE {
+            return Err(invalid_data("Inode maior que o espaço reservado na tabela"));
+        }
+
+        let mut record = vec![0u8; INODE_RECORD_SIZE];
+        record[CHECKSUM_SIZE..CHECKSUM_SIZE + content.len()].copy_from_slice(&content);
+        let checksum = crc32c(&record[CHECKSUM_SIZE..]);
+        record[..CHECKSUM_SIZE].copy_from_slice(&checksum.to_le_bytes());
+
+        let offset = self.super_block.inode_table_offset + (index * INODE_RECORD_SIZE) as u64;
+        self.file.write_all_at(&record, offset)
+    }
+
+    /// Lê o mapa de bits de Inodes (`inodes` verdadeiro) ou o de blocos de memória.
+    pub fn read_bitmap(&self, inodes: bool) -> io::Result<Vec<u8>> {
+        let (offset, len) = self.bitmap_region(inodes);
+        let mut bytes = vec![0u8; len];
+        self.file.read_exact_at(&mut bytes, offset)?;
+        Ok(bytes)
+    }
+
+    /// Grava a palavra `index` do mapa de bits de Inodes (`inodes` verdadeiro) ou do de blocos de memória.
+    pub fn write_bitmap_word(&self, inodes: bool, index: usize, word: u64) -> io::Result<()> {
+        let (offset, _) = self.bitmap_region(inodes);
+        self.file.write_all_at(&word.to_le_bytes(), offset + (index * 8) as u64)
+    }
+
+    fn bitmap_region(&self, inodes: bool) -> (u64, usize) {
+        if inodes {
+            (self.super_block.inode_bitmap_offset, bitmap_size(self.super_block.inode_count) as usize)
+        } else {
+            (self.super_block.block_bitmap_offset, bitmap_size(self.super_block.block_count) as usize)
+        }
+    }
+
+    /// Lê `data.len()` bytes do journal a partir de `offset`, relativo ao começo da região do journal.
+    pub fn read_journal(&self, offset: u64, data: &mut [u8]) -> io::Result<()> {
+        self.file.read_exact_at(data, self.super_block.journal_offset + offset)
+    }
+
+    pub fn write_journal(&self, offset: u64, data: &[u8]) -> io::Result<()> {
+        self.file.write_all_at(data, self.super_block.journal_offset + offset)
+    }
+
+    /// Garante que tudo o que foi gravado na imagem chegou ao dispositivo de armazenamento.
+    pub fn sync(&self) -> io::Result<()> {
+        self.file.sync_data()
+    }
+
+    /// Lê o bloco de memória `index` para `data`, que deve ter o tamanho de um bloco. Retorna um erro se o conteúdo
+    /// lido não conferir com o checksum do bloco.
+    pub fn read_block(&self, index: usize, data: &mut [u8]) -> io::Result<()> {
+        self.file.read_exact_at(data, self.block_offset(index))?;
+
+        let mut checksum = [0u8; CHECKSUM_SIZE];
+        self.file.read_exact_at(&mut checksum, self.checksum_offset(index))?;
+
+        if checksum_matches(&checksum, data) {
+            Ok(())
+        } else {
+            Err(invalid_data(&format!("checksum do bloco {} não confere", index)))
+        }
+    }
+
+    /// Grava o bloco de memória `index` e o seu checksum.
+    pub fn write_block(&self, index: usize, data: &[u8]) -> io::Result<()> {
+        self.file.write_all_at(data, self.block_offset(index))?;
+        self.file.write_all_at(&crc32c(data).to_le_bytes(), self.checksum_offset(index))
+    }
+
+    fn block_offset(&self, index: usize) -> u64 {
+        self.super_block.data_offset + index as u64 * self.super_block.block_size
+    }
+
+    fn checksum_offset(&self, index: usize) -> u64 {
+        self.super_block.checksum_table_offset + (index * CHECKSUM_SIZE) as u64
+    }
+}
@@ -0,0 +1,191 @@
+use std::io;
+use serde::{Serialize, Deserialize};
+use bincode::{serialize, deserialize, serialized_size};
+use crate::image::Image;
+use crate::persistence::Inode;
+
+/// Assinatura do cabeçalho do journal
+const JOURNAL_MAGIC: [u8; 8] = *b"RISOSJNL";
+/// Assinatura de cada transação gravada no journal
+const TRANSACTION_MAGIC: u32 = 0x5249_534a;
+/// Espaço reservado para o cabeçalho no começo da região do journal; as transações são gravadas logo depois
+const JOURNAL_HEADER_SIZE: u64 = 4096;
+
+/// Cabeçalho do journal. `sequence` é o número da primeira transação que ainda não foi aplicada na imagem: somente
+/// transações a partir dele, em sequência, são refeitas ao montar o disco.
+#[derive(Serialize, Deserialize)]
+struct JournalHeader {
+    magic: [u8; 8],
+    sequence: u64
+}
+
+/// Cabeçalho gravado antes de cada transação. A transação só é considerada completa se o checksum do seu conteúdo
+/// conferir, o que descarta transações gravadas pela metade.
+#[derive(Serialize, Deserialize)]
+struct TransactionHeader {
+    magic: u32,
+    length: u64,
+    checksum: u64
+}
+
+/// Alterações feitas por uma operação do FS: o novo conteúdo de cada Inode, palavra dos mapas de bits e bloco de
+/// memória alterado. Como os valores são completos, aplicar a mesma transação mais de uma vez não causa problemas.
+#[derive(Serialize, Deserialize, Default)]
+pub struct Transaction {
+    pub sequence: u64,
+    pub inodes: Vec<(usize, Option<Inode>)>,
+    pub inode_bitmap: Vec<(usize, u64)>,
+    pub block_bitmap: Vec<(usize, u64)>,
+    #[serde(with = "crate::serialization::blocks")]
+    pub blocks: Vec<(usize, Vec<u8>)>
+}
+
+impl Transaction {
+    pub fn is_empty(&self) -> bool {
+        self.inodes.is_empty() && self.inode_bitmap.is_empty() && self.block_bitmap.is_empty() && self.blocks.is_empty()
+    }
+
+    /// Grava as alterações da transação nas suas posições definitivas da imagem.
+    pub fn apply(&self, image: &Image) -> io::Result<()> {
+        for (index, inode) in self.inodes.iter() {
+            image.write_inode(*index, inode)?;
+        }
+
+        for (index, word) in self.inode_bitmap.iter() {
+            image.write_bitmap_word(true, *index, *word)?;
+        }
+
+        for (index, word) in self.block_bitmap.iter() {
+            image.write_bitmap_word(false, *index, *word)?;
+        }
+
+        for (block_index, data) in self.blocks.iter() {
+            image.write_block(*block_index, data)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Journal de escrita antecipada guardado na região de journal da imagem. Cada transação é gravada no fim do journal
+/// antes de ser aplicada na imagem; quando o journal enche (ou o disco é desmontado) as transações são aplicadas e o
+/// journal volta a ficar vazio. Depois de uma queda, as transações completas são refeitas ao montar o disco.
+pub struct Journal {
+    /// Número da próxima transação
+    sequence: u64,
+    /// Posição, relativa à região do journal, onde a próxima transação será gravada
+    position: u64,
+    capacity: u64
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Checksum de 64 bits (FNV-1a) do conteúdo de uma transação.
+fn checksum(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3))
+}
+
+impl Journal {
+    /// Lê o journal da imagem e retorna as transações completas que ainda precisam ser aplicadas, em ordem. Um journal
+    /// sem cabeçalho válido (imagem recém-criada) é considerado vazio e, a não ser com `read_only`, é reiniciado.
+    pub fn open(image: &Image, read_only: bool) -> io::Result<(Journal, Vec<Transaction>)> {
+        let capacity = image.super_block.journal_size;
+
+        let mut header = vec![0u8; serialized_size(&JournalHeader { magic: JOURNAL_MAGIC, sequence: 0 }).unwrap() as usize];
+        image.read_journal(0, &mut header)?;
+
+        let sequence = match deserialize::<JournalHeader>(&header) {
+            Ok(header) if header.magic == JOURNAL_MAGIC => header.sequence,
+            _ => {
+                let mut journal = Journal { sequence: 1, position: JOURNAL_HEADER_SIZE, capacity };
+                if !read_only {
+                    journal.reset(image)?;
+                }
+                return Ok((journal, Vec::new()));
+            }
+        };
+
+        let transaction_header_size = serialized_size(&TransactionHeader { magic: 0, length: 0, checksum: 0 }).unwrap();
+        let mut journal = Journal { sequence, position: JOURNAL_HEADER_SIZE, capacity };
+        let mut transactions: Vec<Transaction> = Vec::new();
+
+        // Lê transações até encontrar uma incompleta, de outra sequência ou o fim do journal
+        while journal.position + transaction_header_size <= capacity {
+            let mut header = vec![0u8; transaction_header_size as usize];
+            image.read_journal(journal.position, &mut header)?;
+
+            let header: TransactionHeader = match deserialize(&header) {
+                Ok(header) => header,
+                Err(_) => break
+            };
+
+            let end = journal.position + transaction_header_size + header.length;
+            if header.magic != TRANSACTION_MAGIC || end > capacity {
+                break;
+            }
+
+            let mut content = vec![0u8; header.length as usize];
+            image.read_journal(journal.position + transaction_header_size, &mut content)?;
+
+            if checksum(&content) != header.checksum {
+                break;
+            }
+
+            let transaction: Transaction = deserialize(&content).map_err(|_| invalid_data("transação inválida"))?;
+            if transaction.sequence != journal.sequence {
+                break;
+            }
+
+            transactions.push(transaction);
+            journal.sequence += 1;
+            journal.position = end;
+        }
+
+        Ok((journal, transactions))
+    }
+
+    /// Tamanho da maior transação que cabe no journal vazio, em bytes.
+    pub fn max_transaction_size(&self) -> u64 {
+        let transaction_header_size = serialized_size(&TransactionHeader { magic: 0, length: 0, checksum: 0 }).unwrap();
+        self.capacity.saturating_sub(JOURNAL_HEADER_SIZE + transaction_header_size)
+    }
+
+    /// Indica se o journal não possui transações ainda não aplicadas.
+    pub fn is_empty(&self) -> bool {
+        self.position == JOURNAL_HEADER_SIZE
+    }
+
+    /// Grava `transaction` no fim do journal, numerando-a. Retorna `false`, sem gravar nada, se ela não couber no
+    /// espaço que resta.
+    pub fn append(&mut self, image: &Image, transaction: &mut Transaction) -> io::Result<bool> {
+        transaction.sequence = self.sequence;
+        let content = serialize(transaction).map_err(|_| invalid_data("transação inválida"))?;
+
+        let header = TransactionHeader { magic: TRANSACTION_MAGIC, length: content.len() as u64, checksum: checksum(&content) };
+        let mut record = serialize(&header).map_err(|_| invalid_data("transação inválida"))?;
+        record.extend_from_slice(&content);
+
+        if self.position + record.len() as u64 > self.capacity {
+            return Ok(false);
+        }
+
+        image.write_journal(self.position, &record)?;
+        self.position += record.len() as u64;
+        self.sequence += 1;
+        Ok(true)
+    }
+
+    /// Esvazia o journal depois que todas as transações foram aplicadas na imagem. O cabeçalho passa a apontar para a
+    /// próxima transação, então as antigas que continuam gravadas no journal são ignoradas.
+    pub fn reset(&mut self, image: &Image) -> io::Result<()> {
+        let header = serialize(&JournalHeader { magic: JOURNAL_MAGIC, sequence: self.sequence })
+            .map_err(|_| invalid_data("cabeçalho do journal inválido"))?;
+
+        image.write_journal(0, &header)?;
+        image.sync()?;
+        self.position = JOURNAL_HEADER_SIZE;
+        Ok(())
+    }
+}
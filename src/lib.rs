@@ -0,0 +1,12 @@
+//! Disco do RisosFS: formato da imagem, journal e estruturas em memória, compartilhados pelo daemon e pelas
+//! ferramentas de linha de comando.
+#[macro_use]
+extern crate serde_big_array;
+pub mod bitmap;
+pub mod checksum;
+pub mod image;
+pub mod journal;
+pub mod options;
+pub mod persistence;
+pub mod serialization;
+pub mod uuid;
@@ -1,43 +1,389 @@
 extern crate fuse;
-#[macro_use]
-extern crate serde_big_array;
-mod persistence;
-mod serialization;
+mod daemon;
+mod permissions;
 
-use fuse::{Filesystem, Request, ReplyCreate, ReplyEmpty, ReplyAttr, ReplyEntry, ReplyOpen, ReplyData, ReplyDirectory, ReplyWrite, FileType, FileAttr};
+use fuse::{Filesystem, Request, ReplyCreate, ReplyEmpty, ReplyAttr, ReplyEntry, ReplyOpen, ReplyData, ReplyDirectory, ReplyWrite, ReplyStatfs, FileType, FileAttr};
 // https://www.gnu.org/software/libc/manual/html_node/Error-Codes.html
-use libc::{ENOSYS, ENOENT, EIO, EISDIR, ENOSPC};
+use libc::{c_int, ENOENT, EIO, EISDIR, ENOSPC, EINVAL, EEXIST, ENOTDIR, ENOTEMPTY, ENAMETOOLONG, EACCES, EPERM, EBADF, EBUSY, PATH_MAX};
 use time::{Timespec};
 use std::env;
-use std::mem;
+use std::io;
 use std::ffi::OsStr;
-use crate::persistence::{Disk, Inode};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::Duration;
+use std::collections::HashMap;
+use risos_fs::persistence::{Disk, Inode, NAME_MAX, name_hash};
+use crate::permissions::{R_OK, W_OK, X_OK, S_ISUID, S_ISGID};
+
+// Flags de renameat2(2)
+const RENAME_NOREPLACE: u32 = 1;
+const RENAME_EXCHANGE: u32 = 2;
+/// Nome da imagem procurada dentro do ponto de montagem quando nenhuma imagem é informada
+const DEFAULT_IMAGE_NAME: &str = ".imagem.risos";
+/// Arquivos em que versões antigas do RisosFS guardavam o disco, dentro do ponto de montagem
+const LEGACY_FILES: [&str; 3] = [".inode.risos", ".disco.risos", ".bitmap.risos"];
+/// Intervalo padrão entre os checkpoints periódicos, em segundos
+const DEFAULT_CHECKPOINT_INTERVAL: u64 = 30;
 
 struct RisosFS {
-    disk: Disk
+    disk: Disk,
+    open_files: HashMap<u64, OpenFile>,
+    next_fh: u64
+}
+
+/// Arquivo aberto através de open/create, identificado pelo `fh` devolvido ao kernel.
+struct OpenFile {
+    ino: u64,
+    flags: u32
 }
 
 impl RisosFS {
-    /// Inicializa o FS com o tamanho especificado em `memory_size` com blocos de memória de tamanho
-    /// `block_size`.
-    fn new(root_path: String) -> Self {
-        let max_files: usize = 1024;
-        let memory_size: usize = 1024 * 1024 * 1024;
-        let block_size: usize = max_files * (mem::size_of::<Box<[Inode]>>() + mem::size_of::<Inode>());
+    /// Inicializa o FS com o disco guardado na imagem `image_path`, que já deve ter sido formatada.
+    fn new(image_path: &Path) -> io::Result<Self> {
+        let disk = Disk::new(image_path)?;
+
+        Ok(RisosFS {
+            disk,
+            open_files: HashMap::new(),
+            next_fh: 1
+        })
+    }
+
+    /// Verifica se o usuário que fez a requisição possui as permissões `mask` sobre o Inode `ino`.
+    fn check_access(&self, req: &Request, ino: u64, mask: u32) -> Result<(), c_int> {
+        match self.disk.get_inode(ino) {
+            Some(inode) => {
+                if permissions::has_access(&inode.attributes, req.uid(), req.gid(), mask) {
+                    Ok(())
+                } else {
+                    Err(EACCES) // “Permission denied.”
+                }
+            },
+            None => Err(self.disk.inode_error(ino))
+        }
+    }
+
+    /// Verifica se o usuário que fez a requisição pode remover a entrada `child` do diretório `parent`: é preciso
+    /// permissão de escrita e execução no diretório e, se ele tiver o sticky bit, ser dono de um dos dois.
+    fn check_removal(&self, req: &Request, parent: u64, child: &FileAttr) -> Result<(), c_int> {
+        self.check_access(req, parent, W_OK | X_OK)?;
+
+        let parent_inode = self.disk.get_inode(parent).ok_or_else(|| self.disk.inode_error(parent))?;
+        if permissions::can_remove_entry(&parent_inode.attributes, child, req.uid()) {
+            Ok(())
+        } else {
+            Err(EPERM) // “Operation not permitted.”
+        }
+    }
+
+    /// Dono e grupo de um novo arquivo criado em `parent`. Diretórios com o bit setgid repassam o seu grupo.
+    fn new_file_owner(&self, req: &Request, parent: u64) -> (u32, u32, bool) {
+        match self.disk.get_inode(parent) {
+            Some(inode) if inode.attributes.perm & S_ISGID != 0 => (req.uid(), inode.attributes.gid, true),
+            _ => (req.uid(), req.gid(), false)
+        }
+    }
+
+    /// Cria um novo Inode do tipo `kind` chamado `name` dentro do diretório `parent`, pertencente ao usuário que fez
+    /// a requisição, e retorna os seus atributos.
+    fn create_inode(
+        &mut self,
+        req: &Request,
+        parent: u64,
+        name: &OsStr,
+        kind: FileType,
+        perm: u16
+    ) -> Result<FileAttr, c_int> {
+        self.check_access(req, parent, W_OK | X_OK)?;
+
+        if name.len() > NAME_MAX {
+            return Err(ENAMETOOLONG); // “Filename too long.”
+        }
+
+        if self.disk.find_inode_in_references_by_name(parent, name)?.is_some() {
+            return Err(EEXIST); // “File exists.”
+        }
+
+        let ino = self.disk.find_ino_available().ok_or(ENOSPC)?; // “No space left on device.”
+
+        let (uid, gid, inherit_setgid) = self.new_file_owner(req, parent);
+        let perm = if inherit_setgid && kind == FileType::Directory { perm | S_ISGID } else { perm };
+
+        let ts = time::now().to_timespec();
+        let attr = FileAttr {
+            ino,
+            size: 0,
+            blocks: 0,
+            atime: ts,
+            mtime: ts,
+            ctime: ts,
+            crtime: ts,
+            kind,
+            perm,
+            // Diretórios começam com o "." e a entrada no pai
+            nlink: if kind == FileType::Directory { 2 } else { 1 },
+            uid,
+            gid,
+            rdev: 0,
+            flags: 0,
+        };
+
+        let inode = Inode {
+            attributes: attr,
+            references: [None; 128],
+            parent
+        };
+
+        self.disk.write_inode(inode);
+
+        // Adiciona o Inode criado como uma entrada do diretório "pai", desfazendo a criação se não houver espaço
+        if let Err(err) = self.disk.add_directory_entry(parent, name, ino, kind) {
+            self.disk.clear_inode(ino);
+            return Err(err);
+        }
+
+        // O ".." do novo diretório é mais uma ligação para o pai
+        if kind == FileType::Directory {
+            self.disk.increment_nlink(parent);
+        }
+
+        if let Some(parent_inode) = self.disk.get_inode_as_mut(parent) {
+            parent_inode.attributes.mtime = ts;
+            parent_inode.attributes.ctime = ts;
+        }
+
+        Ok(attr)
+    }
+
+    /// Verifica se ainda há algum `fh` aberto para o Inode `ino`.
+    fn is_open(&self, ino: u64) -> bool {
+        self.open_files.values().any(|file| file.ino == ino)
+    }
+
+    /// Remove uma ligação do arquivo `ino`. O Inode e os seus blocos de memória só são liberados quando a última
+    /// ligação é removida e não há mais nenhum `fh` aberto para ele; caso contrário isso acontece no release.
+    fn drop_link(&mut self, ino: u64) {
+        let nlink = self.disk.decrement_nlink(ino);
+
+        if let Some(inode) = self.disk.get_inode_as_mut(ino) {
+            inode.attributes.ctime = time::now().to_timespec();
+        }
+
+        if nlink == 0 && !self.is_open(ino) {
+            self.disk.free_inode(ino);
+        }
+    }
+
+    /// Remove o diretório vazio `ino` do diretório `parent`, que perde a ligação do ".." do filho.
+    fn drop_directory(&mut self, parent: u64, ino: u64) {
+        self.disk.free_inode(ino);
+        self.disk.decrement_nlink(parent);
+    }
+
+    /// Cria uma nova entrada `newname` no diretório `newparent` para o arquivo já existente `ino`.
+    fn link_entry(&mut self, req: &Request, ino: u64, newparent: u64, newname: &OsStr) -> Result<FileAttr, c_int> {
+        let attr = match self.disk.get_inode(ino) {
+            Some(inode) => inode.attributes,
+            None => return Err(self.disk.inode_error(ino))
+        };
+
+        // Ligações extras para diretórios criariam ciclos na árvore
+        if attr.kind == FileType::Directory {
+            return Err(EPERM);
+        }
+
+        self.check_access(req, newparent, W_OK | X_OK)?;
+
+        if self.disk.find_inode_in_references_by_name(newparent, newname)?.is_some() {
+            return Err(EEXIST);
+        }
+
+        self.disk.add_directory_entry(newparent, newname, ino, attr.kind)?;
+        self.disk.increment_nlink(ino);
+
+        let ts = time::now().to_timespec();
+        if let Some(parent_inode) = self.disk.get_inode_as_mut(newparent) {
+            parent_inode.attributes.mtime = ts;
+            parent_inode.attributes.ctime = ts;
+        }
+
+        let inode = self.disk.get_inode_as_mut(ino).unwrap();
+        inode.attributes.ctime = ts;
+        Ok(inode.attributes)
+    }
+
+    /// Registra um novo arquivo aberto e retorna o seu `fh`.
+    fn open_file(&mut self, ino: u64, flags: u32) -> u64 {
+        let fh = self.next_fh;
+        self.next_fh += 1;
+        self.open_files.insert(fh, OpenFile { ino, flags });
+        fh
+    }
+
+    /// Verifica se `fh` foi aberto para o arquivo `ino` com as permissões `mask`.
+    fn check_handle(&self, fh: u64, ino: u64, mask: u32) -> Result<(), c_int> {
+        match self.open_files.get(&fh) {
+            Some(file) if file.ino == ino => {
+                if permissions::open_access_mask(file.flags) & mask == mask {
+                    Ok(())
+                } else {
+                    Err(EACCES)
+                }
+            },
+            _ => Err(EBADF) // “Bad file descriptor.”
+        }
+    }
+
+    /// Move a entrada `name` do diretório `parent` para `newname` no diretório `newparent`, seguindo a semântica
+    /// de rename(2): um arquivo de destino existente é substituído, a não ser que `RENAME_NOREPLACE` seja
+    /// informado, e `RENAME_EXCHANGE` troca as duas entradas de lugar.
+    fn rename_entry(
+        &mut self,
+        req: &Request,
+        parent: u64,
+        name: &OsStr,
+        newparent: u64,
+        newname: &OsStr,
+        flags: u32
+    ) -> Result<(), c_int> {
+        if newname.len() > NAME_MAX {
+            return Err(ENAMETOOLONG);
+        }
+
+        self.check_access(req, parent, X_OK)?;
+        self.check_access(req, newparent, X_OK)?;
+
+        let (source_ino, source_kind, source_attr) = match self.disk.find_inode_in_references_by_name(parent, name)? {
+            Some(inode) => (inode.attributes.ino, inode.attributes.kind, inode.attributes),
+            None => return Err(ENOENT)
+        };
+
+        let target = self.disk.find_inode_in_references_by_name(newparent, newname)?
+            .map(|inode| (inode.attributes.ino, inode.attributes.kind, inode.attributes));
+
+        self.check_removal(req, parent, &source_attr)?;
+        match target {
+            Some((_, _, target_attr)) => self.check_removal(req, newparent, &target_attr)?,
+            None => self.check_access(req, newparent, W_OK | X_OK)?
+        }
+
+        let target = target.map(|(ino, kind, _)| (ino, kind));
+
+        if flags & RENAME_NOREPLACE != 0 && target.is_some() {
+            return Err(EEXIST);
+        }
+
+        // Um diretório não pode ser movido para dentro dele mesmo
+        if source_kind == FileType::Directory && self.disk.is_descendant(source_ino, newparent) {
+            return Err(EINVAL);
+        }
+
+        if flags & RENAME_EXCHANGE != 0 {
+            let (target_ino, target_kind) = target.ok_or(ENOENT)?;
+
+            if target_kind == FileType::Directory && self.disk.is_descendant(target_ino, parent) {
+                return Err(EINVAL);
+            }
+
+            // Os nomes continuam nos mesmos diretórios, só os Inodes para os quais eles apontam são trocados
+            self.disk.replace_directory_entry(parent, name, target_ino, target_kind);
+            self.disk.replace_directory_entry(newparent, newname, source_ino, source_kind);
+
+            if parent != newparent {
+                // O ".." de cada diretório trocado passa a apontar para o outro pai
+                if source_kind == FileType::Directory {
+                    self.disk.decrement_nlink(parent);
+                    self.disk.increment_nlink(newparent);
+                    self.disk.get_inode_as_mut(source_ino).unwrap().parent = newparent;
+                }
+
+                if target_kind == FileType::Directory {
+                    self.disk.decrement_nlink(newparent);
+                    self.disk.increment_nlink(parent);
+                    self.disk.get_inode_as_mut(target_ino).unwrap().parent = parent;
+                }
+            }
+        } else {
+            if let Some((target_ino, target_kind)) = target {
+                // Origem e destino já são o mesmo arquivo: nada a fazer
+                if target_ino == source_ino {
+                    return Ok(());
+                }
 
-        let disk = Disk::new(root_path, memory_size, block_size);
+                if source_kind == FileType::Directory && target_kind != FileType::Directory {
+                    return Err(ENOTDIR);
+                }
+
+                if source_kind != FileType::Directory && target_kind == FileType::Directory {
+                    return Err(EISDIR);
+                }
+
+                if target_kind == FileType::Directory && !self.disk.is_directory_empty(target_ino) {
+                    return Err(ENOTEMPTY);
+                }
+            } else {
+                // A nova entrada é criada antes de remover a antiga para que o arquivo não se perca sem espaço livre
+                self.disk.add_directory_entry(newparent, newname, source_ino, source_kind)?;
+            }
+
+            // A partir daqui nenhuma verificação pode falhar, então o destino é substituído de uma só vez
+            self.disk.remove_directory_entry(parent, name);
+
+            if let Some((target_ino, target_kind)) = target {
+                self.disk.replace_directory_entry(newparent, newname, source_ino, source_kind);
+
+                if target_kind == FileType::Directory {
+                    self.drop_directory(newparent, target_ino);
+                } else {
+                    self.drop_link(target_ino);
+                }
+            }
+
+            if parent != newparent && source_kind == FileType::Directory {
+                self.disk.decrement_nlink(parent);
+                self.disk.increment_nlink(newparent);
+                self.disk.get_inode_as_mut(source_ino).unwrap().parent = newparent;
+            }
 
-        RisosFS {
-            disk
+            if let Some(inode) = self.disk.get_inode_as_mut(source_ino) {
+                inode.attributes.ctime = time::now().to_timespec();
+            }
         }
+
+        let ts = time::now().to_timespec();
+        for ino in [parent, newparent].iter() {
+            if let Some(inode) = self.disk.get_inode_as_mut(*ino) {
+                inode.attributes.mtime = ts;
+                inode.attributes.ctime = ts;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Garante que as alterações feitas no arquivo `ino` chegaram ao dispositivo de armazenamento. Todas as
+    /// alterações do disco são gravadas juntas no journal, então as dos demais arquivos também são sincronizadas.
+    fn sync_inode(&mut self, ino: u64) -> Result<(), c_int> {
+        if self.disk.get_inode(ino).is_none() {
+            return Err(self.disk.inode_error(ino));
+        }
+
+        self.disk.sync().map_err(|e| {
+            println!("Erro ao sincronizar o disco! {}", e);
+            EIO
+        })
     }
 }
 
 impl Drop for RisosFS {
     fn drop(&mut self) {
         println!("\nsaving content...");
-        &self.disk.write_to_disk();
-        println!("success!");
+        match self.disk.write_to_disk() {
+            Ok(()) => println!("success!"),
+            Err(e) => println!("Erro ao salvar o disco! {}", e)
+        }
     }
 }
 
@@ -45,28 +391,40 @@ impl Drop for RisosFS {
 impl Filesystem for RisosFS {
     fn lookup(
         &mut self, 
-        _req: &Request, 
+        req: &Request, 
         parent: u64, 
         name: &OsStr, 
         reply: ReplyEntry
     ) {
         println!("lookup(parent={:?}, name={:?})", parent, name);
-        let file_name = name.to_str().unwrap();
-        let inode = self.disk.find_inode_in_references_by_name(parent, file_name);
+
+        // Procurar um nome dentro de um diretório exige permissão de execução nele
+        if let Err(e) = self.check_access(req, parent, X_OK) {
+            reply.error(e);
+            return;
+        }
+
+        if name.len() > NAME_MAX {
+            reply.error(ENAMETOOLONG);
+            return;
+        }
+
+        let inode = self.disk.find_inode_in_references_by_name(parent, name);
 
         match inode {
-            Some(inode) => {
+            Ok(Some(inode)) => {
                 let ttl = time::now().to_timespec();
                 println!("        - lookup(parent={:?}, attr={:?})", parent, inode.attributes);
                 reply.entry(&ttl, &inode.attributes, 0)
             },
-            None => reply.error(ENOENT) // “No such file or directory.”
+            Ok(None) => reply.error(ENOENT), // “No such file or directory.”
+            Err(e) => reply.error(e)
         }
     }
 
     fn create(
         &mut self, 
-        _req: &Request, 
+        req: &Request, 
         parent: u64, 
         name: &OsStr, 
         mode: u32, 
@@ -75,67 +433,20 @@ impl Filesystem for RisosFS {
     ) {
         println!("create(name={:?}, mode={}, flags={})", name, mode, flags);
 
-        let ref_index = self.disk.find_index_of_empty_reference_in_inode(parent);
-        // Se não houver mais espaço no vetor de references, indica que não é possível alocar mais arquivos dentro da pasta
-        if ref_index == None {
-            println!("Não é possível criar mais arquivos nesse diretório!");
-            reply.error(EIO); // “Input/output error.”
-            return;
-        }
-
-        let ino_available = self.disk.find_ino_available();
-        let memory_block_index = self.disk.find_index_of_empty_memory_block();
+        // O kernel já aplica o umask do processo em `mode` antes de repassá-lo ao FS
+        match self.create_inode(req, parent, name, FileType::RegularFile, (mode & 0o7777) as u16) {
+            Ok(attr) => {
+                if let Err(e) = self.disk.commit() {
+                    reply.error(e);
+                    return;
+                }
 
-        if ino_available == None || memory_block_index == None {
-            reply.error(ENOSPC); // “No space left on device.”
-            return;
+                let ts = time::now().to_timespec();
+                let fh = self.open_file(attr.ino, flags);
+                reply.created(&ts, &attr, 1, fh, flags)
+            },
+            Err(e) => reply.error(e)
         }
-
-        let ino_available = ino_available.unwrap();
-        let memory_block_index = memory_block_index.unwrap();
-
-        let ts = time::now().to_timespec();
-
-        let attr = FileAttr {
-            ino: ino_available,
-            size: 0,
-            blocks: 1,
-            atime: ts,
-            mtime: ts,
-            ctime: ts,
-            crtime: ts,
-            kind: FileType::RegularFile,
-            perm: 0o755,
-            nlink: 0,
-            uid: 0,
-            gid: 0,
-            rdev: 0,
-            flags,
-        };
-        
-        let name = name.to_str().unwrap();
-        let name: Vec<char> = name.chars().collect();
-
-        let mut name_char = ['\0'; 64];
-        name_char[..name.len()].clone_from_slice(&name);
-
-        let mut inode = Inode {
-            name: name_char,
-            attributes: attr,
-            references: [None; 128]
-        };
-
-        inode.references[0] = Some(memory_block_index);
-
-        self.disk.write_inode(inode);
-        let content: Box<[u8]> = Box::default();
-        self.disk.write_content_as_bytes(memory_block_index, content);
-
-        // Adiciona a referência de inode criado no vetor references do inode "pai" (do diretório)
-        let ref_index = ref_index.unwrap();
-        self.disk.write_reference_in_inode(parent, ref_index, ino_available as usize);
-
-        reply.created(&ts, &attr, 1, ino_available, flags)
     }
 
     fn fsync(
@@ -147,42 +458,147 @@ impl Filesystem for RisosFS {
         reply: ReplyEmpty
     ) { 
         println!("fsync(ino={}, fh={}, datasync={})", ino, fh, datasync);
-        reply.error(ENOSYS);
+
+        match self.sync_inode(ino) {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(e)
+        }
+    }
+
+    fn fsyncdir(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        fh: u64,
+        datasync: bool,
+        reply: ReplyEmpty
+    ) {
+        println!("fsyncdir(ino={}, fh={}, datasync={})", ino, fh, datasync);
+
+        match self.disk.get_inode(ino) {
+            Some(inode) if inode.attributes.kind != FileType::Directory => reply.error(ENOTDIR), // “Not a directory.”
+            _ => match self.sync_inode(ino) {
+                Ok(()) => reply.ok(),
+                Err(e) => reply.error(e)
+            }
+        }
     }
 
     fn setattr(
         &mut self, 
-        _req: &Request, 
+        req: &Request, 
         ino: u64, 
-        _mode: Option<u32>, 
+        mode: Option<u32>, 
         uid: Option<u32>, 
         gid: Option<u32>, 
         size: Option<u64>, 
         atime: Option<Timespec>, 
         mtime: Option<Timespec>, 
-        _fh: Option<u64>, 
+        fh: Option<u64>, 
         crtime: Option<Timespec>, 
         _chgtime: Option<Timespec>, 
         _bkuptime: Option<Timespec>, 
         flags: Option<u32>, 
         reply: ReplyAttr
     ) {
-        println!("setattr(ino={})", ino);
+        println!("setattr(ino={}, mode={:?}, uid={:?}, gid={:?}, size={:?})", ino, mode, uid, gid, size);
+
+        let attr = match self.disk.get_inode(ino) {
+            Some(inode) => inode.attributes,
+            None => {
+                reply.error(self.disk.inode_error(ino));
+                return;
+            }
+        };
+
+        let is_owner = permissions::is_owner(&attr, req.uid());
+        let can_write = permissions::has_access(&attr, req.uid(), req.gid(), W_OK);
+
+        // chmod, chown e chgrp: somente o dono ou o root, e apenas o root pode entregar o arquivo para outro usuário.
+        // O dono só pode trocar o grupo para o seu próprio grupo.
+        let denied = (mode.is_some() && !is_owner)
+            || uid.is_some_and(|uid| uid != attr.uid && req.uid() != 0)
+            || gid.is_some_and(|gid| gid != attr.gid && req.uid() != 0 && !(is_owner && gid == req.gid()))
+            || ((crtime.is_some() || flags.is_some()) && !is_owner);
+
+        if denied {
+            reply.error(EPERM); // “Operation not permitted.”
+            return;
+        }
+
+        // Truncar exige permissão de escrita, a não ser que o arquivo já tenha sido aberto para escrita
+        let truncate_allowed = can_write || fh.is_some_and(|fh| self.check_handle(fh, ino, W_OK).is_ok());
+        let times_allowed = is_owner || can_write;
+
+        if (size.is_some() && !truncate_allowed) || ((atime.is_some() || mtime.is_some()) && !times_allowed) {
+            reply.error(EACCES); // “Permission denied.”
+            return;
+        }
+
+        if let Some(size) = size {
+            match self.disk.get_inode(ino) {
+                Some(inode) if inode.attributes.kind == FileType::Directory => {
+                    reply.error(EISDIR); // “Is a directory.”
+                    return;
+                },
+                Some(_) => {
+                    // Trunca ou estende o conteúdo do arquivo, e não apenas o tamanho informado nos atributos
+                    if let Err(e) = self.disk.resize_content(ino, size) {
+                        reply.error(e);
+                        return;
+                    }
+
+                    let inode = self.disk.get_inode_as_mut(ino).unwrap();
+                    let ts = time::now().to_timespec();
+                    inode.attributes.mtime = ts;
+                    inode.attributes.ctime = ts;
+                },
+                None => {
+                    reply.error(ENOENT);
+                    return;
+                }
+            }
+        }
+
         let inode = self.disk.get_inode_as_mut(ino);
         
         match inode {
             Some(inode) => {
-                if let Some(size) = size { inode.attributes.size = size; }
+                if let Some(mode) = mode {
+                    let mut perm = (mode & 0o7777) as u16;
+                    // Usuários comuns que não pertencem ao grupo do arquivo não podem ligar o bit setgid
+                    if req.uid() != 0 && req.gid() != inode.attributes.gid {
+                        perm &= !S_ISGID;
+                    }
+                    inode.attributes.perm = perm;
+                }
+
                 if let Some(atime) = atime { inode.attributes.atime = atime; }
                 if let Some(mtime) = mtime { inode.attributes.mtime = mtime; }
                 if let Some(crtime) = crtime { inode.attributes.crtime = crtime; }
+
+                let owner_changed = uid.is_some_and(|uid| uid != inode.attributes.uid)
+                    || gid.is_some_and(|gid| gid != inode.attributes.gid);
                 if let Some(gid) = gid { inode.attributes.gid = gid; }
                 if let Some(uid) = uid { inode.attributes.uid = uid; }
+                // Trocar o dono de um arquivo remove os bits setuid e setgid
+                if owner_changed && inode.attributes.kind != FileType::Directory {
+                    inode.attributes.perm &= !(S_ISUID | S_ISGID);
+                }
+
                 if let Some(flags) = flags { inode.attributes.flags = flags; }
 
+                if mode.is_some() || uid.is_some() || gid.is_some() {
+                    inode.attributes.ctime = time::now().to_timespec();
+                }
+
                 let ttl = time::now().to_timespec();
+                let attr = inode.attributes;
 
-                reply.attr(&ttl, &inode.attributes)
+                match self.disk.commit() {
+                    Ok(()) => reply.attr(&ttl, &attr),
+                    Err(e) => reply.error(e)
+                }
             },
             None => reply.error(ENOENT)
         }
@@ -201,104 +617,155 @@ impl Filesystem for RisosFS {
                 let ttl = time::now().to_timespec();
                 reply.attr(&ttl, &inode.attributes);
             },
-            None => reply.error(ENOENT)
+            None => reply.error(self.disk.inode_error(ino))
         }
     }
 
     fn mkdir(
         &mut self, 
-        _req: &Request, 
+        req: &Request, 
         parent: u64, 
         name: &OsStr, 
-        _mode: u32, 
+        mode: u32, 
         reply: ReplyEntry
     ) {
-        let reference_index = self.disk.find_index_of_empty_reference_in_inode(parent);
-        
-        match reference_index {
-            Some(reference_index) => {
-
-                let ino = self.disk.find_ino_available();
-                match ino {
-                    Some(ino) => {
-                        let ts = time::now().to_timespec();
-                        let attr = FileAttr {
-                            ino: ino as u64,
-                            size: 0,
-                            blocks: 1,
-                            atime: ts,
-                            mtime: ts,
-                            ctime: ts,
-                            crtime: ts,
-                            kind: FileType::Directory,
-                            perm: 0o755,
-                            nlink: 0,
-                            uid: 0,
-                            gid: 0,
-                            rdev: 0,
-                            flags: 0,
-                        };
-
-                        let name = name.to_str().unwrap();
-                        let name: Vec<char> = name.chars().collect();
-
-                        let mut name_char = ['\0'; 64];
-                        name_char[..name.len()].clone_from_slice(&name);
-
-                        let inode = Inode {
-                            name: name_char,
-                            attributes: attr,
-                            references: [None; 128]
-                        };
-
-                        self.disk.write_inode(inode);
-                        self.disk.write_reference_in_inode(parent, reference_index, ino as usize);
-
-                        reply.entry(&ts, &attr, 0);
-                    },
-                    None => reply.error(ENOSPC) // “No space left on device.”
-                }
+        println!("mkdir(parent={}, name={:?}, mode={})", parent, name, mode);
+
+        match self.create_inode(req, parent, name, FileType::Directory, (mode & 0o7777) as u16) {
+            Ok(attr) => match self.disk.commit() {
+                Ok(()) => {
+                    let ts = time::now().to_timespec();
+                    reply.entry(&ts, &attr, 0)
+                },
+                Err(e) => reply.error(e)
             },
-            None => { println!("Limite de arquivos dentro da pasta atingido!"); reply.error(EIO); }
+            Err(e) => reply.error(e)
         }
     }
 
     fn rmdir(
         &mut self, 
-        _req: &Request, 
+        req: &Request, 
         parent: u64, 
         name: &OsStr, 
         reply: ReplyEmpty
     ) {
-        let name = name.to_str().unwrap();
-        let inode = self.disk.find_inode_in_references_by_name(parent, name);
+        if let Err(e) = self.check_access(req, parent, X_OK) {
+            reply.error(e);
+            return;
+        }
+
+        // O "." não pode ser removido e o ".." nunca está vazio
+        if name == "." {
+            reply.error(EINVAL); // “Invalid argument.”
+            return;
+        }
+
+        if name == ".." {
+            reply.error(ENOTEMPTY);
+            return;
+        }
+
+        let inode = self.disk.find_inode_in_references_by_name(parent, name).map(|inode| inode.map(|inode| inode.attributes));
 
         match inode {
-            Some(inode) => {
-                let ino = inode.attributes.ino;
-                self.disk.clear_reference_in_inode(parent, ino as usize);
-                self.disk.clear_inode(ino);
+            Ok(Some(attr)) => {
+                if attr.kind != FileType::Directory {
+                    reply.error(ENOTDIR); // “Not a directory.”
+                    return;
+                }
+
+                if attr.ino == 1 {
+                    reply.error(EBUSY); // “Device or resource busy.”
+                    return;
+                }
+
+                if let Err(e) = self.check_removal(req, parent, &attr) {
+                    reply.error(e);
+                    return;
+                }
+
+                // Remover um diretório com arquivos deixaria todos eles sem nenhuma entrada que os alcance
+                if !self.disk.is_directory_empty(attr.ino) {
+                    reply.error(ENOTEMPTY); // “Directory not empty.”
+                    return;
+                }
+
+                self.disk.remove_directory_entry(parent, name);
+                self.drop_directory(parent, attr.ino);
 
-                reply.ok();
+                match self.disk.commit() {
+                    Ok(()) => reply.ok(),
+                    Err(e) => reply.error(e)
+                }
             },
-            None => reply.error(EIO) // "Input/output error."
+            Ok(None) => reply.error(ENOENT), // “No such file or directory.”
+            Err(e) => reply.error(e)
         }
     }
 
     fn open(
         &mut self,
-        _req: &Request,
+        req: &Request,
         ino: u64,
         flags: u32,
         reply: ReplyOpen
     ) {
         println!("open(ino={}, flags={})", ino, flags);
 
-        let inode = self.disk.get_inode(ino);
+        match self.check_access(req, ino, permissions::open_access_mask(flags)) {
+            Ok(()) => {
+                let fh = self.open_file(ino, flags);
+                reply.opened(fh, flags)
+            },
+            Err(e) => reply.error(e)
+        }
+    }
 
-        match inode {
-            Some(_) => reply.opened(ino, flags),
-            None => reply.error(ENOSYS)
+    fn flush(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        fh: u64,
+        _lock_owner: u64,
+        reply: ReplyEmpty
+    ) {
+        println!("flush(ino={}, fh={})", ino, fh);
+
+        if !self.open_files.contains_key(&fh) {
+            reply.error(EBADF); // “Bad file descriptor.”
+            return;
+        }
+
+        // Grava no journal o que ainda estiver só em memória, sem esperar o dispositivo como no fsync
+        match self.disk.commit() {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(e)
+        }
+    }
+
+    fn release(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        fh: u64,
+        _flags: u32,
+        _lock_owner: u64,
+        _flush: bool,
+        reply: ReplyEmpty
+    ) {
+        println!("release(ino={}, fh={})", ino, fh);
+        self.open_files.remove(&fh);
+
+        // Arquivos removidos enquanto ainda estavam abertos só são liberados quando o último `fh` é fechado
+        let unlinked = self.disk.get_inode(ino).is_some_and(|inode| inode.attributes.nlink == 0);
+        if unlinked && !self.is_open(ino) {
+            self.disk.free_inode(ino);
+        }
+
+        match self.disk.commit() {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(e)
         }
     }
 
@@ -313,17 +780,48 @@ impl Filesystem for RisosFS {
     ) {
         println!("read(ino={}, fh={}, offset={}, size={})", ino, fh, offset, size);
 
-        let memory_block = self.disk.get_content_as_bytes(ino as usize);
-        
-        match memory_block {
-            Some(memory_block) => reply.data(memory_block),
-            None => reply.error(EIO)
+        // As permissões foram verificadas no open: aqui basta o arquivo ter sido aberto para leitura
+        if let Err(e) = self.check_handle(fh, ino, R_OK) {
+            reply.error(e);
+            return;
+        }
+
+        if offset < 0 {
+            reply.error(EINVAL); // “Invalid argument.”
+            return;
+        }
+
+        match self.disk.read_content(ino, offset as u64, size as usize) {
+            Ok(content) => reply.data(&content),
+            Err(e) => reply.error(e)
+        }
+    }
+
+    fn opendir(
+        &mut self,
+        req: &Request,
+        ino: u64,
+        flags: u32,
+        reply: ReplyOpen
+    ) {
+        println!("opendir(ino={}, flags={})", ino, flags);
+
+        match self.disk.get_inode(ino) {
+            Some(inode) if inode.attributes.kind == FileType::Directory => (),
+            Some(_) => { reply.error(ENOTDIR); return; }, // “Not a directory.”
+            None => { reply.error(self.disk.inode_error(ino)); return; }
+        }
+
+        // Listar um diretório exige permissão de leitura sobre ele
+        match self.check_access(req, ino, R_OK) {
+            Ok(()) => reply.opened(0, flags),
+            Err(e) => reply.error(e)
         }
     }
 
     fn readdir(
         &mut self, 
-        _req: &Request, 
+        req: &Request, 
         ino: u64, 
         fh: u64, 
         offset: i64, 
@@ -331,129 +829,431 @@ impl Filesystem for RisosFS {
     ) {
         println!("readdir(ino={}, fh={}, offset={})", ino, fh, offset);
 
-        // Pequeno "ajuste técnico" para mostrar o "." e ".." na primeira pasta.
-        if ino == 1 {
-            if offset == 0 {
-                reply.add(1, 0, FileType::Directory, ".");
-                reply.add(1, 1, FileType::Directory, "..");
-            }
+        let parent = match self.disk.get_inode(ino) {
+            Some(inode) if inode.attributes.kind == FileType::Directory => inode.parent,
+            Some(_) => { reply.error(ENOTDIR); return; }, // “Not a directory.”
+            None => { println!("ERROR ino={:?}", ino); reply.error(self.disk.inode_error(ino)); return; }
+        };
+
+        if let Err(e) = self.check_access(req, ino, R_OK) {
+            reply.error(e);
+            return;
         }
 
-        // Inode "pai" (o diretório)
-        let inode: Option<&Inode> = self.disk.get_inode(ino);
+        // O offset de cada entrada é o da próxima a ser lida: 1 e 2 para o "." e o "..", e para as demais o hash do
+        // nome deslocado, que segue a ordem em que o diretório guarda as entradas e não muda com novas entradas
+        if offset < 1 && reply.add(ino, 1, FileType::Directory, ".") {
+            reply.ok();
+            return;
+        }
 
-        // Offset representado como o tamanho inteiro do Inode, pois de uma só vez será lido todo o conteúdo
-        // do diretório. Caso o offset seja o mesmo que o tamanho do inode parent, então dá um "ok" e retorna o conteúdo.
-        if mem::size_of_val(&inode) == offset as usize {
+        if offset < 2 && reply.add(parent, 2, FileType::Directory, "..") {
             reply.ok();
             return;
         }
 
-        match inode {
-            Some(inode) => {
-                let references = inode.references;
-                // Percorre pelo vetor de referências do Inode pai. Cada posição indica um arquivo que está presente
-                // no diretório.
-                for ino in references.iter() {
-
-                    if let Some(ino) = ino {
-                        let inode = self.disk.get_inode(*ino as u64);
-
-                        if let Some(inode_data) = inode {
-                            if inode_data.attributes.ino == 1 {
-                                continue;
-                            }
-
-                            let name = inode_data.name.iter().collect::<String>();
-                            println!("    - readdir(ino={}, name={})", inode_data.attributes.ino, name);
-                            let offset = mem::size_of_val(&inode) as i64;
-                            reply.add(inode_data.attributes.ino, offset, inode_data.attributes.kind, name);
-                        }
-                    }
+        // Primeiro hash ainda não devolvido ao kernel
+        let min_hash = match (offset.max(2) as u64 - 2).checked_mul(4) {
+            Some(min_hash) => min_hash,
+            None => { reply.ok(); return; }
+        };
+
+        // Percorre o diretório um bucket por vez, a partir do bucket de `min_hash`, até o buffer da resposta encher
+        let mut next = Some(min_hash);
+        while let Some(min_hash) = next {
+            let (entries, following) = match self.disk.read_directory_bucket(ino, min_hash) {
+                Some(bucket) => bucket,
+                None => { reply.error(EIO); return; }
+            };
+
+            for entry in entries.iter() {
+                let name = OsStr::from_bytes(&entry.name);
+                println!("    - readdir(ino={}, name={:?})", entry.ino, name);
+                if reply.add(entry.ino, entry_offset(&entry.name), entry.kind, name) {
+                    reply.ok();
+                    return;
                 }
+            }
 
-                reply.ok()
-            },
-            None => { println!("ERROR ino={:?}", ino); reply.error(ENOENT) }
+            next = following;
         }
+
+        reply.ok()
     }
 
     fn write(
         &mut self, 
         _req: &Request, 
         ino: u64, 
-        _fh: u64, 
+        fh: u64, 
         offset: i64, 
         data: &[u8], 
         _flags: u32, 
         reply: ReplyWrite
     ) {
         println!("write(ino={}, offset={}, data={})", ino, offset, data.len());
-        let inode = self.disk.get_inode_as_mut(ino);
-        let content: Box<[u8]> = data.to_vec().into_boxed_slice();
 
-        match inode {
-            Some(inode) => {
-                inode.attributes.size = data.len() as u64;
-                let index = (ino as usize) - 1;
-                self.disk.write_content_as_bytes(index, content);
-                reply.written(data.len() as u32);
+        if let Err(e) = self.check_handle(fh, ino, W_OK) {
+            reply.error(e);
+            return;
+        }
+
+        if offset < 0 {
+            reply.error(EINVAL); // “Invalid argument.”
+            return;
+        }
+
+        match self.disk.write_content(ino, offset as u64, data) {
+            Ok(written) => {
+                let inode = self.disk.get_inode_as_mut(ino).unwrap();
+                let ts = time::now().to_timespec();
+                inode.attributes.mtime = ts;
+                inode.attributes.ctime = ts;
+
+                match self.disk.commit() {
+                    Ok(()) => reply.written(written as u32),
+                    Err(e) => reply.error(e)
+                }
             },
-            None => {
+            Err(ENOENT) => {
                 println!("Inode não foi encontrado");
                 reply.error(ENOENT);
-            }
+            },
+            Err(e) => reply.error(e)
         }
     }
 
     fn unlink(
         &mut self, 
-        _req: &Request, 
+        req: &Request, 
         parent: u64, 
         name: &OsStr, 
         reply: ReplyEmpty
     ) {
-        let name = name.to_str().unwrap();
-        let inode = self.disk.find_inode_in_references_by_name(parent, name);
+        if let Err(e) = self.check_access(req, parent, X_OK) {
+            reply.error(e);
+            return;
+        }
+
+        let inode = self.disk.find_inode_in_references_by_name(parent, name).map(|inode| inode.map(|inode| inode.attributes));
 
         match inode {
-            Some(inode) => {
-                if inode.attributes.kind == FileType::Directory {
+            Ok(Some(attr)) => {
+                if attr.kind == FileType::Directory {
                     reply.error(EISDIR);
+                } else if let Err(e) = self.check_removal(req, parent, &attr) {
+                    reply.error(e);
                 } else {
-                    let ino = inode.attributes.ino;
-                    let memory_block_index = (ino as usize) - 1;
-                    self.disk.clear_inode(ino);
-                    self.disk.clear_memory_block(memory_block_index);
-                    self.disk.clear_reference_in_inode(parent, ino as usize);
-                    reply.ok()
+                    self.disk.remove_directory_entry(parent, name);
+                    self.drop_link(attr.ino);
+
+                    match self.disk.commit() {
+                        Ok(()) => reply.ok(),
+                        Err(e) => reply.error(e)
+                    }
+                }
+            },
+            Ok(None) => reply.error(ENOENT), // “No such file or directory.”
+            Err(e) => reply.error(e)
+        }
+    }
+
+    fn rename(
+        &mut self,
+        req: &Request,
+        parent: u64,
+        name: &OsStr,
+        newparent: u64,
+        newname: &OsStr,
+        reply: ReplyEmpty
+    ) {
+        println!("rename(parent={}, name={:?}, newparent={}, newname={:?})", parent, name, newparent, newname);
+
+        // A versão do rust-fuse utilizada não repassa as flags de renameat2(2), então aqui é sempre um rename simples
+        match self.rename_entry(req, parent, name, newparent, newname, 0) {
+            Ok(()) => match self.disk.commit() {
+                Ok(()) => reply.ok(),
+                Err(e) => reply.error(e)
+            },
+            Err(e) => reply.error(e)
+        }
+    }
+
+    #[cfg(target_os = "macos")]
+    fn exchange(
+        &mut self,
+        req: &Request,
+        parent: u64,
+        name: &OsStr,
+        newparent: u64,
+        newname: &OsStr,
+        _options: u64,
+        reply: ReplyEmpty
+    ) {
+        println!("exchange(parent={}, name={:?}, newparent={}, newname={:?})", parent, name, newparent, newname);
+
+        match self.rename_entry(req, parent, name, newparent, newname, RENAME_EXCHANGE) {
+            Ok(()) => match self.disk.commit() {
+                Ok(()) => reply.ok(),
+                Err(e) => reply.error(e)
+            },
+            Err(e) => reply.error(e)
+        }
+    }
+
+    fn link(
+        &mut self,
+        req: &Request,
+        ino: u64,
+        newparent: u64,
+        newname: &OsStr,
+        reply: ReplyEntry
+    ) {
+        println!("link(ino={}, newparent={}, newname={:?})", ino, newparent, newname);
+
+        match self.link_entry(req, ino, newparent, newname) {
+            Ok(attr) => match self.disk.commit() {
+                Ok(()) => {
+                    let ts = time::now().to_timespec();
+                    reply.entry(&ts, &attr, 0)
+                },
+                Err(e) => reply.error(e)
+            },
+            Err(e) => reply.error(e)
+        }
+    }
+
+    fn symlink(
+        &mut self,
+        req: &Request,
+        parent: u64,
+        name: &OsStr,
+        link: &Path,
+        reply: ReplyEntry
+    ) {
+        println!("symlink(parent={}, name={:?}, link={:?})", parent, name, link);
+
+        // O destino do link é guardado como conteúdo do arquivo, nos blocos de memória do Inode
+        let target = link.as_os_str().as_bytes();
+        if target.len() > PATH_MAX as usize {
+            reply.error(ENAMETOOLONG);
+            return;
+        }
+
+        let attr = match self.create_inode(req, parent, name, FileType::Symlink, 0o777) {
+            Ok(attr) => attr,
+            Err(e) => {
+                reply.error(e);
+                return;
+            }
+        };
+
+        // Um destino escrito pela metade não serve para nada, então uma escrita incompleta é tratada como falta de espaço
+        let written = self.disk.write_content(attr.ino, 0, target)
+            .and_then(|written| if written == target.len() { Ok(()) } else { Err(ENOSPC) });
+
+        match written {
+            Ok(()) => {
+                let ts = time::now().to_timespec();
+                let attr = self.disk.get_inode(attr.ino).unwrap().attributes;
+
+                match self.disk.commit() {
+                    Ok(()) => reply.entry(&ts, &attr, 0),
+                    Err(e) => reply.error(e)
+                }
+            },
+            Err(e) => {
+                // Desfaz a criação do Inode caso não haja espaço para guardar o destino
+                self.disk.remove_directory_entry(parent, name);
+                self.disk.free_inode(attr.ino);
+                reply.error(e)
+            }
+        }
+    }
+
+    fn readlink(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        reply: ReplyData
+    ) {
+        println!("readlink(ino={})", ino);
+
+        match self.disk.get_inode(ino) {
+            Some(inode) if inode.attributes.kind == FileType::Symlink => {
+                let size = inode.attributes.size as usize;
+                match self.disk.read_content(ino, 0, size) {
+                    Ok(target) => reply.data(&target),
+                    Err(e) => reply.error(e)
                 }
             },
-            None => reply.error(EIO)
+            Some(_) => reply.error(EINVAL), // “Invalid argument.”
+            None => reply.error(self.disk.inode_error(ino))
         }
     }
 
+    fn access(
+        &mut self,
+        req: &Request,
+        ino: u64,
+        mask: u32,
+        reply: ReplyEmpty
+    ) {
+        println!("access(ino={}, mask={})", ino, mask);
+
+        // F_OK (0) só verifica se o arquivo existe
+        match self.check_access(req, ino, mask) {
+            Ok(()) => reply.ok(),
+            Err(e) => reply.error(e)
+        }
+    }
+
+    fn statfs(
+        &mut self,
+        _req: &Request,
+        ino: u64,
+        reply: ReplyStatfs
+    ) {
+        println!("statfs(ino={})", ino);
+
+        let block_size = self.disk.get_block_size() as u32;
+        let free_blocks = self.disk.free_memory_blocks() as u64;
+
+        reply.statfs(
+            self.disk.total_memory_blocks() as u64,
+            free_blocks,
+            // Não há blocos reservados para o root, então todos os livres estão disponíveis
+            free_blocks,
+            self.disk.total_inodes() as u64,
+            self.disk.free_inodes() as u64,
+            block_size,
+            NAME_MAX as u32,
+            block_size
+        );
+    }
+
     // fn truncate
     // fn utimens
 }
 
+/// Offset de readdir da entrada `name`: os 62 bits mais significativos do hash do nome somados a 3, de forma que
+/// fique positivo e depois dos offsets do "." e do "..". Entradas com o mesmo hash recebem o mesmo offset.
+fn entry_offset(name: &[u8]) -> i64 {
+    (name_hash(name) >> 2) as i64 + 3
+}
+
 fn main() {
-    let mountpoint = match env::args().nth(1) {
+    let program = env::args().nth(0).unwrap();
+    let usage = format!(
+        "Usage: {} [--image <IMAGE>] [--checkpoint-interval <SECONDS>] [--scrub] <MOUNTPOINT>",
+        program
+    );
+
+    let mut mountpoint: Option<String> = None;
+    let mut image_path: Option<PathBuf> = None;
+    let mut checkpoint_interval = Some(Duration::from_secs(DEFAULT_CHECKPOINT_INTERVAL));
+    let mut scrub = false;
+    let mut args = env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--image" => match args.next() {
+                Some(path) => image_path = Some(PathBuf::from(path)),
+                None => {
+                    println!("{}", usage);
+                    return;
+                }
+            },
+            // Intervalo entre os checkpoints periódicos; 0 desativa os checkpoints
+            "--checkpoint-interval" => match args.next().and_then(|seconds| seconds.parse::<u64>().ok()) {
+                Some(0) => checkpoint_interval = None,
+                Some(seconds) => checkpoint_interval = Some(Duration::from_secs(seconds)),
+                None => {
+                    println!("{}", usage);
+                    return;
+                }
+            },
+            // Confere os checksums de todos os blocos da imagem antes de montar
+            "--scrub" => scrub = true,
+            _ if mountpoint.is_none() => mountpoint = Some(arg),
+            _ => {
+                println!("{}", usage);
+                return;
+            }
+        }
+    }
+
+    let mountpoint = match mountpoint {
         Some(path) => path,
         None => {
-            println!("Usage: {} <MOUNTPOINT>", env::args().nth(0).unwrap());
+            println!("{}", usage);
             return;
         }
     };
 
-    let fs = RisosFS::new(mountpoint.clone());
+    // Sem `--image`, a imagem fica dentro do próprio ponto de montagem, escondida pelo FS enquanto ele está montado,
+    // e por isso o FS precisa ser montado sobre um diretório que não está vazio
+    let (image_path, options) = match image_path {
+        Some(path) => (path, Vec::new()),
+        None => (Path::new(&mountpoint).join(DEFAULT_IMAGE_NAME), vec!["-o", "nonempty"])
+    };
+
+    // Versões antigas guardavam o disco em arquivos separados dentro do ponto de montagem, em um formato que esta versão
+    // não lê. Sem a imagem, quem atualizou o RisosFS precisa saber que os arquivos antigos não foram perdidos
+    let legacy_files: Vec<&str> = LEGACY_FILES.iter()
+        .filter(|name| Path::new(&mountpoint).join(name).exists())
+        .copied()
+        .collect();
+
+    if !legacy_files.is_empty() {
+        if !image_path.exists() {
+            println!(
+                "O ponto de montagem {} possui um disco salvo por uma versão antiga do RisosFS ({}), que esta versão não \
+                 consegue ler.",
+                mountpoint, legacy_files.join(", ")
+            );
+            println!(
+                "Monte-o com a versão antiga e copie os arquivos para fora dele, ou apague os arquivos antigos e crie \
+                 uma imagem vazia com o mkfs-risos."
+            );
+            process::exit(1);
+        }
+
+        println!("Os arquivos {} de uma versão antiga do RisosFS foram ignorados", legacy_files.join(", "));
+    }
+
+    // A imagem é criada somente pelo mkfs-risos: um caminho errado não pode virar um disco novo e vazio
+    if !image_path.exists() {
+        println!(
+            "A imagem {} não existe! Crie-a antes com `mkfs-risos {}`.",
+            image_path.display(), image_path.display()
+        );
+        process::exit(1);
+    }
+
+    let fs = match RisosFS::new(&image_path) {
+        Ok(fs) => fs,
+        Err(e) => {
+            println!("Erro ao carregar o disco! {}", e);
+            process::exit(1);
+        }
+    };
+
+    if scrub {
+        println!("Verificando os checksums da imagem...");
+        match fs.disk.scrub() {
+            0 => println!("Nenhum bloco corrompido encontrado"),
+            corrupted => println!("{} blocos corrompidos encontrados!", corrupted)
+        }
+    }
 
-    let options = ["-o", "nonempty"]
+    let options = options
         .iter()
         .map(|o| o.as_ref())
         .collect::<Vec<&OsStr>>();
 
-    println!("RisosFS started!");
-
-    fuse::mount(fs, &mountpoint, &options).unwrap();
-}
\ No newline at end of file
+    if let Err(e) = daemon::run(fs, Path::new(&mountpoint), &options, checkpoint_interval) {
+        println!("Erro ao montar o FS! {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,23 @@
+/// Tamanho padrão de um disco novo, em bytes
+pub const DEFAULT_DISK_SIZE: u64 = 1024 * 1024 * 1024;
+/// Tamanho padrão dos blocos de memória de um disco novo, em bytes
+pub const DEFAULT_BLOCK_SIZE: usize = 4096;
+/// Quantidade de bytes do disco para cada Inode de um disco novo, se a quantidade de Inodes não for informada
+pub const BYTES_PER_INODE: u64 = 64 * 1024;
+
+/// Quantidade padrão de Inodes de um disco novo com `disk_size` bytes: um para cada `BYTES_PER_INODE` bytes.
+pub fn default_inode_count(disk_size: u64) -> usize {
+    (disk_size / BYTES_PER_INODE).max(1) as usize
+}
+
+/// Lê um tamanho ou quantidade, aceitando os sufixos K, M e G (potências de 1024).
+pub fn parse_size(text: &str) -> Option<u64> {
+    let (number, multiplier) = match text.chars().last()?.to_ascii_uppercase() {
+        'K' => (&text[..text.len() - 1], 1 << 10),
+        'M' => (&text[..text.len() - 1], 1 << 20),
+        'G' => (&text[..text.len() - 1], 1 << 30),
+        _ => (text, 1)
+    };
+
+    number.parse::<u64>().ok()?.checked_mul(multiplier)
+}
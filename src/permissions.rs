@@ -0,0 +1,149 @@
+use fuse::{FileAttr, FileType};
+use libc::{O_ACCMODE, O_RDONLY, O_WRONLY, O_TRUNC};
+
+// Bits de permissão pedidos em access(2)
+pub const R_OK: u32 = 4;
+pub const W_OK: u32 = 2;
+pub const X_OK: u32 = 1;
+
+// Bits especiais do modo de um arquivo
+pub const S_ISUID: u16 = 0o4000;
+pub const S_ISGID: u16 = 0o2000;
+pub const S_ISVTX: u16 = 0o1000;
+
+/// Verifica se o usuário `uid` do grupo `gid` possui as permissões pedidas em `mask` (combinação de `R_OK`, `W_OK` e
+/// `X_OK`) sobre o arquivo descrito por `attr`, usando os bits de dono, grupo ou outros conforme o caso.
+pub fn has_access(attr: &FileAttr, uid: u32, gid: u32, mask: u32) -> bool {
+    let mask = mask & 0o7;
+
+    // O root ignora as permissões de leitura e escrita, mas só executa arquivos com ao menos um bit de execução
+    if uid == 0 {
+        return mask & X_OK == 0 || attr.kind == FileType::Directory || attr.perm & 0o111 != 0;
+    }
+
+    let perm = u32::from(attr.perm);
+    let granted = if uid == attr.uid {
+        (perm >> 6) & 0o7
+    } else if gid == attr.gid {
+        (perm >> 3) & 0o7
+    } else {
+        perm & 0o7
+    };
+
+    granted & mask == mask
+}
+
+/// Somente o dono do arquivo ou o root podem alterar o seu modo, dono e datas arbitrárias.
+pub fn is_owner(attr: &FileAttr, uid: u32) -> bool {
+    uid == 0 || uid == attr.uid
+}
+
+/// Verifica se `uid` pode remover (ou renomear) a entrada `child` do diretório `dir`. Em diretórios com o sticky bit
+/// somente o dono da entrada, o dono do diretório ou o root podem fazê-lo.
+pub fn can_remove_entry(dir: &FileAttr, child: &FileAttr, uid: u32) -> bool {
+    dir.perm & S_ISVTX == 0 || uid == 0 || uid == dir.uid || uid == child.uid
+}
+
+/// Converte as flags de open(2) nas permissões necessárias para abrir o arquivo.
+pub fn open_access_mask(flags: u32) -> u32 {
+    let flags = flags as i32;
+    let mask = match flags & O_ACCMODE {
+        O_RDONLY => R_OK,
+        O_WRONLY => W_OK,
+        _ => R_OK | W_OK
+    };
+
+    if flags & O_TRUNC != 0 { mask | W_OK } else { mask }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libc::{O_RDWR, O_CREAT};
+    use time::Timespec;
+
+    /// Atributos de um arquivo do tipo `kind` com o modo `perm`, pertencente ao usuário 1000 do grupo 100.
+    fn attr(kind: FileType, perm: u16) -> FileAttr {
+        let ts = Timespec::new(0, 0);
+        FileAttr {
+            ino: 2,
+            size: 0,
+            blocks: 0,
+            atime: ts,
+            mtime: ts,
+            ctime: ts,
+            crtime: ts,
+            kind,
+            perm,
+            nlink: 1,
+            uid: 1000,
+            gid: 100,
+            rdev: 0,
+            flags: 0,
+        }
+    }
+
+    #[test]
+    fn uses_owner_group_or_other_bits() {
+        let file = attr(FileType::RegularFile, 0o640);
+
+        // Dono: rw-
+        assert!(has_access(&file, 1000, 100, R_OK | W_OK));
+        assert!(!has_access(&file, 1000, 100, X_OK));
+
+        // Grupo: r--, mesmo que o dono tivesse mais permissões
+        assert!(has_access(&file, 1001, 100, R_OK));
+        assert!(!has_access(&file, 1001, 100, W_OK));
+
+        // Outros: ---
+        assert!(!has_access(&file, 1001, 101, R_OK));
+        assert!(has_access(&file, 1001, 101, 0));
+
+        // O dono usa somente os seus bits, mesmo que os outros tenham mais permissões
+        let owner_denied = attr(FileType::RegularFile, 0o077);
+        assert!(!has_access(&owner_denied, 1000, 100, R_OK));
+        assert!(has_access(&owner_denied, 1001, 100, R_OK | W_OK | X_OK));
+    }
+
+    #[test]
+    fn root_needs_an_execute_bit_only_for_files() {
+        let file = attr(FileType::RegularFile, 0o600);
+        assert!(has_access(&file, 0, 0, R_OK | W_OK));
+        assert!(!has_access(&file, 0, 0, X_OK));
+
+        // Basta um bit de execução em qualquer uma das classes
+        let executable = attr(FileType::RegularFile, 0o001);
+        assert!(has_access(&executable, 0, 0, R_OK | W_OK | X_OK));
+
+        // Diretórios sempre podem ser percorridos pelo root
+        let dir = attr(FileType::Directory, 0o000);
+        assert!(has_access(&dir, 0, 0, R_OK | W_OK | X_OK));
+    }
+
+    #[test]
+    fn sticky_directory_allows_only_owners_to_remove() {
+        let child = attr(FileType::RegularFile, 0o644);
+        let mut dir = attr(FileType::Directory, 0o777);
+        dir.uid = 2000;
+
+        // Sem o sticky bit qualquer um (com permissão de escrita no diretório) pode remover
+        assert!(can_remove_entry(&dir, &child, 3000));
+
+        dir.perm |= S_ISVTX;
+        assert!(!can_remove_entry(&dir, &child, 3000));
+        assert!(can_remove_entry(&dir, &child, 1000)); // dono da entrada
+        assert!(can_remove_entry(&dir, &child, 2000)); // dono do diretório
+        assert!(can_remove_entry(&dir, &child, 0));
+    }
+
+    #[test]
+    fn open_flags_map_to_access_mask() {
+        assert_eq!(open_access_mask(O_RDONLY as u32), R_OK);
+        assert_eq!(open_access_mask(O_WRONLY as u32), W_OK);
+        assert_eq!(open_access_mask(O_RDWR as u32), R_OK | W_OK);
+        assert_eq!(open_access_mask((O_WRONLY | O_CREAT) as u32), W_OK);
+
+        // Truncar exige escrita mesmo quando o arquivo é aberto somente para leitura
+        assert_eq!(open_access_mask((O_RDONLY | O_TRUNC) as u32), R_OK | W_OK);
+    }
+}
@@ -1,228 +1,826 @@
 use fuse::{FileAttr};
+use std::io;
 use std::str;
 use std::mem;
-use std::fs::File;
-use std::io::prelude::*;
+use std::ops::Range;
 use std::path::Path;
-use std::fs::OpenOptions;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use serde::{Serialize, Deserialize};
-use crate::serialization::FileAttrDef;
-use bincode::{serialize, deserialize};
+use crate::serialization::{FileAttrDef, FileTypeDef};
+use crate::bitmap::Bitmap;
+use crate::image::{Image, SuperBlock};
+use crate::journal::{Journal, Transaction};
+use bincode::{serialize, deserialize, serialized_size};
 use fuse::{FileType};
+use libc::{c_int, ENOENT, ENOSPC, EFBIG, EIO, ENAMETOOLONG, ENOTDIR};
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+
+mod fsck;
+pub use self::fsck::{CheckSummary, LOST_AND_FOUND, ranges};
 
 big_array! { BigArray; }
 
+/// Tamanho máximo de um nome de arquivo, em bytes
+pub const NAME_MAX: usize = 255;
+/// Tamanho do cabeçalho do índice de um diretório, com a profundidade da tabela, em bytes
+const DIRECTORY_HEADER_SIZE: usize = 8;
+/// Tamanho de cada posição da tabela do índice de um diretório, em bytes
+const SLOT_SIZE: usize = 8;
+/// Profundidade máxima da tabela do índice de um diretório. Uma tabela tão grande não cabe em nenhum disco; o limite
+/// só evita que a quantidade de posições estoure
+const MAX_DIRECTORY_DEPTH: u32 = 48;
+/// Quantidade de references do Inode que apontam direto para blocos com conteúdo
+const DIRECT_REFERENCES: usize = 126;
+/// Reference do Inode que aponta para o bloco de ponteiros simplesmente indireto
+const SINGLE_INDIRECT: usize = 126;
+/// Reference do Inode que aponta para o bloco de ponteiros duplamente indireto
+const DOUBLE_INDIRECT: usize = 127;
+/// Tamanho de cada ponteiro de um bloco de ponteiros, em bytes
+const POINTER_SIZE: usize = 8;
+/// Memória usada pelos blocos em cache, em bytes
+const CACHE_SIZE: usize = 64 * 1024 * 1024;
+/// Quantidade mínima de blocos em cache, mesmo com blocos grandes
+const MIN_CACHE_BLOCKS: usize = 16;
+/// Bytes que uma transação ocupa no journal além das alterações: o número da transação e o tamanho de cada lista
+const TRANSACTION_FIELDS_SIZE: u64 = 40;
+/// Maior tamanho de um Inode alterado gravado no journal: a posição e o Inode com todas as references preenchidas
+const INODE_RECORD_SIZE: u64 = 1280;
+/// Bytes de uma palavra alterada de um mapa de bits gravada no journal: a posição e a palavra
+const WORD_RECORD_SIZE: u64 = 16;
+/// Bytes de um bloco alterado gravado no journal além do seu conteúdo: a posição e o tamanho do conteúdo
+const BLOCK_RECORD_SIZE: u64 = 16;
+/// Espaço de uma transação reservado para os Inodes que uma operação altera depois de conferir o espaço para os seus
+/// blocos
+const TRANSACTION_RESERVE: u64 = 8 * INODE_RECORD_SIZE;
+
 pub struct Disk {
+    /// Arquivo de imagem onde o disco é persistido
+    image: Image,
+    journal: Journal,
+    /// Alterações já gravadas no journal que ainda não foram aplicadas na imagem
+    pending: Checkpoint,
+    /// Indica que alguma transação de `pending` não pôde ser gravada no journal e só existe em memória
+    unjournaled: bool,
+    /// Indica que as alterações não devem ser gravadas, nem no journal nem na imagem
+    read_only: bool,
+    /// Tabela de Inodes, mantida inteira em memória e gravada na imagem Inode a Inode
     super_block: Box<[Option<Inode>]>,
-    memory_blocks: Box<[MemoryBlock]>,
+    /// Posições do `super_block` alteradas desde a última transação
+    dirty_inodes: HashSet<usize>,
+    /// Posições da tabela de Inodes cujo registro na imagem está corrompido. Elas ficam vazias no `super_block` e
+    /// ocupadas no mapa de Inodes até serem regravadas, para que não sejam reutilizadas enquanto alguma entrada de
+    /// diretório ainda pode apontar para elas.
+    corrupted_inodes: HashSet<usize>,
+    /// Blocos de memória lidos da imagem recentemente, limitados a `cache_capacity` blocos
+    block_cache: RefCell<HashMap<usize, Box<[u8]>>>,
+    /// Blocos de memória alterados desde a última transação
+    dirty_blocks: HashMap<usize, Box<[u8]>>,
+    cache_capacity: usize,
+    /// Posições ocupadas do `super_block`
+    inode_bitmap: Bitmap,
+    /// Blocos de memória ocupados
+    block_bitmap: Bitmap,
     max_files: usize,
-    block_size: usize,
-    root_path: String
+    block_size: usize
 }
 
-#[derive(Serialize, Deserialize)]
+/// Valores gravados no journal por transações que ainda não foram aplicadas nas posições definitivas da imagem.
+/// Guardam o estado da última transação de cada Inode, palavra dos mapas de bits e bloco, independente de alterações
+/// posteriores ainda não gravadas no journal.
+#[derive(Default)]
+struct Checkpoint {
+    inodes: HashMap<usize, Option<Inode>>,
+    inode_bitmap: HashMap<usize, u64>,
+    block_bitmap: HashMap<usize, u64>,
+    blocks: HashMap<usize, Box<[u8]>>
+}
+
+impl Checkpoint {
+    fn is_empty(&self) -> bool {
+        self.inodes.is_empty() && self.inode_bitmap.is_empty() && self.block_bitmap.is_empty() && self.blocks.is_empty()
+    }
+
+    /// Acrescenta as alterações de `transaction`, substituindo valores antigos das mesmas posições.
+    fn merge(&mut self, transaction: Transaction) {
+        self.inodes.extend(transaction.inodes);
+        self.inode_bitmap.extend(transaction.inode_bitmap);
+        self.block_bitmap.extend(transaction.block_bitmap);
+        self.blocks.extend(transaction.blocks.into_iter().map(|(block_index, data)| (block_index, data.into_boxed_slice())));
+    }
+
+    /// Converte as alterações de volta em uma transação, para serem aplicadas na imagem.
+    fn into_transaction(self) -> Transaction {
+        Transaction {
+            sequence: 0,
+            inodes: self.inodes.into_iter().collect(),
+            inode_bitmap: self.inode_bitmap.into_iter().collect(),
+            block_bitmap: self.block_bitmap.into_iter().collect(),
+            blocks: self.blocks.into_iter().map(|(block_index, data)| (block_index, data.into_vec())).collect()
+        }
+    }
+}
+
+/// Um Inode guarda direto os `DIRECT_REFERENCES` primeiros blocos do arquivo. Os seguintes ficam em blocos de
+/// ponteiros: os próximos no bloco simplesmente indireto e o restante nos blocos apontados pelo bloco duplamente
+/// indireto. Os ponteiros são gravados como o índice do bloco + 1 em little-endian; um ponteiro zerado indica que
+/// não há bloco.
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Inode {
-    #[serde(with = "BigArray")]
-    pub name: [char; 64],
     #[serde(with = "FileAttrDef")]
     pub attributes: FileAttr,
     #[serde(with = "BigArray")]
-    pub references: [Option<usize>; 128]
+    pub references: [Option<usize>; 128],
+    /// Diretório pai, usado como ".." de um diretório. O root é pai de si mesmo. Como arquivos comuns podem ter
+    /// várias ligações, para eles o valor só indica onde o arquivo foi criado.
+    pub parent: u64
+}
+
+/// Entrada de um diretório, que associa um nome a um Inode. O nome fica na entrada e não no Inode, de forma que um
+/// mesmo arquivo possa aparecer com nomes diferentes (ligações) e nomes não precisem ser UTF-8.
+///
+/// O conteúdo de um diretório é um índice de hash extensível guardado nos seus blocos de memória. Os primeiros
+/// blocos guardam a profundidade `d` da tabela e a tabela, com `2^d` posições: a posição dos `d` bits mais
+/// significativos do hash de um nome aponta para o bloco lógico do bucket com as entradas desse nome. Cada bucket
+/// ocupa um bloco e guarda, ordenadas pelo hash, as entradas cujo hash começa com os mesmos bits. Assim lookup,
+/// criação e remoção leem somente uma posição da tabela e um bloco, e o índice é persistido junto com o restante do
+/// disco.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct DirectoryEntry {
+    #[serde(with = "crate::serialization::bytes")]
+    pub name: Vec<u8>,
+    pub ino: u64,
+    #[serde(with = "FileTypeDef")]
+    pub kind: FileType
 }
 
+/// Bucket do índice de um diretório, gravado em um bloco lógico do diretório
 #[derive(Serialize, Deserialize)]
-pub struct MemoryBlock {
-    data: Option<Box<[u8]>>
+struct Bucket {
+    /// Quantidade de bits do hash que as entradas do bucket têm em comum. Um bucket com profundidade menor que a da
+    /// tabela é apontado por `2^(profundidade da tabela - depth)` posições consecutivas
+    depth: u32,
+    entries: Vec<DirectoryEntry>
 }
 
-impl Disk {
+/// Bucket encontrado no índice de um diretório
+struct BucketLocation {
+    /// Profundidade da tabela do índice
+    table_depth: u32,
+    /// Posição da tabela usada para encontrar o bucket
+    slot: usize,
+    /// Bloco lógico do diretório onde o bucket está gravado
+    logical: usize,
+    bucket: Bucket
+}
 
-    /// Inicializa um disco virtual com o tamanho total especificado em `memory_size_in_bytes` e com cada bloco contendo um tamanho fixo definido em `block_size`.
-    /// O número de blocos alocados é definido pela expressão `memory_size_in_bytes / block_size`.
-    pub fn new(
-        root_path: String,
-        memory_size_in_bytes: usize,
-        block_size: usize
-    ) -> Disk {
-        // Quantidade de blocos de memória
-        // O -1 é referente ao "superblock", que possui o mesmo tamanho de um MemoryBlock
-        let memory_block_quantity: usize = (memory_size_in_bytes / block_size) - 1;
-        // Está sendo considerado o tamanho do ponteiro do Box além do tamanho da struct de Inode
-        let inode_size = mem::size_of::<Box<[Inode]>>() + mem::size_of::<Inode>();
-        let max_files = block_size / inode_size;
-
-        let disk_file_path = format!("{}/.disco.risos", &root_path);
-        let inode_table_file_path = format!("{}/.inode.risos", &root_path);
-
-        // Tenta ler o arquivo do disco, se nao existir cria um novo
-        let mut memory_blocks: Vec<MemoryBlock>;
-        let mut super_block: Vec<Option<Inode>>;
-
-        if Path::new(&disk_file_path).exists() && Path::new(&inode_table_file_path).exists() {
-            println!("Disco existente encontrado! Carregando...");
-
-            let mut ser_inodes: Vec<u8> = Vec::new();
-            let mut ser_disk: Vec<u8> = Vec::new();
-
-            File::open(&inode_table_file_path).unwrap().read_to_end(&mut ser_inodes).unwrap();
-            File::open(&disk_file_path).unwrap().read_to_end(&mut ser_disk).unwrap();
-
-            super_block = if &ser_inodes.len() > &0 {
-                deserialize(&ser_inodes).expect("Erro lendo disco persistido!")
-             } else {
-                Vec::new()
-            };
+/// Onde fica o número do bloco de memória de um bloco lógico de um arquivo
+enum BlockPath {
+    /// Na posição indicada de `references`
+    Direct(usize),
+    /// Na posição indicada do bloco simplesmente indireto
+    Single(usize),
+    /// Na segunda posição indicada do bloco apontado pela primeira posição do bloco duplamente indireto
+    Double(usize, usize)
+}
 
-            memory_blocks = if &ser_disk.len() > &0 {
-                deserialize(&ser_disk).expect("Erro lendo disco persistido!")
-            } else {
-                Vec::new()
-            };
+/// Blocos de memória usados por um arquivo
+pub struct FileMap {
+    /// Blocos de conteúdo, cada um junto com o bloco lógico do arquivo que ele guarda, em ordem de bloco lógico
+    pub data_blocks: Vec<(usize, usize)>,
+    pub pointer_blocks: Vec<usize>
+}
 
-            // Se o numero de blocos do disco existente for maior que o do disco a ser criado, termina a execuçao
-            if memory_block_quantity < memory_blocks.len() {
-                panic!("O disco existente e maior que o disco atual! Tente inicializar com um disco de tamanho maior!");
-            }
-        } else {
-            File::create(&disk_file_path).expect("Erro criando arquivos para persistencia!");
-            File::create(&inode_table_file_path).expect("Erro criando arquivos para persistencia!");
-
-            super_block = Vec::with_capacity(1);
-            memory_blocks = Vec::new();
-
-            let ts = time::now().to_timespec();
-            let attr = FileAttr {
-                ino: 1,
-                size: 0,
-                blocks: 0,
-                atime: ts,
-                mtime: ts,
-                ctime: ts,
-                crtime: ts,
-                kind: FileType::Directory,
-                perm: 0o755,
-                nlink: 0,
-                uid: 0,
-                gid: 0,
-                rdev: 0,
-                flags: 0,
-            };
+impl Disk {
 
-            let mut name = ['\0'; 64];
-            name[0] = '.';
+    /// Formata um disco novo no arquivo de imagem `image_path` com a geometria de `header`, calculada por
+    /// `SuperBlock::format`. A geometria fica gravada no superbloco da imagem, de onde é lida toda vez que o disco é
+    /// carregado. Uma imagem que já exista em `image_path` é sobrescrita.
+    pub fn format(image_path: &Path, header: SuperBlock) -> io::Result<()> {
+        Image::create(image_path, header)?;
 
-            let initial_inode = Inode {
-                name,
-                attributes: attr,
-                references: [None; 128]
-            };
+        // Carregar o disco novo cria o diretório raiz
+        Disk::new(image_path)?.write_to_disk()
+    }
 
-            super_block.push(Some(initial_inode));
-        };
+    /// Carrega o disco guardado no arquivo de imagem `image_path`, criado com `Disk::format`, usando a geometria
+    /// gravada no seu superbloco. Retorna um erro se a imagem não existir, não puder ser lida ou estiver corrompida.
+    ///
+    /// Um disco novo recebe o diretório raiz, e os arquivos removidos enquanto estavam abertos são liberados.
+    pub fn new(image_path: &Path) -> io::Result<Disk> {
+        println!("Carregando o disco {}...", image_path.display());
+        let mut disk = Disk::load(image_path, false)?;
 
-        // Instanciando em branco outras posiçoes possiveis para maior velocidade
-        for _ in super_block.len()..max_files {
-            let value: Option<Inode> = Option::None;
-            super_block.push(value);
+        if disk.is_inode_corrupted(1) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData, "o registro do root está corrompido; execute o fsck-risos com --repair"
+            ));
         }
 
-        for _ in memory_blocks.len()..memory_block_quantity {
-            let value: MemoryBlock = MemoryBlock { data: Option::None };
-            memory_blocks.push(value);
+        // Disco novo: cria o diretório raiz, que pertence a quem criou o disco para que ele consiga criar arquivos sem
+        // ser root
+        if disk.super_block[0].is_none() {
+            let root = directory_inode(1, 1, 0o755, unsafe { libc::getuid() }, unsafe { libc::getgid() });
+            disk.write_inode(root);
         }
+        disk.inode_bitmap.set(0);
+
+        // Arquivos removidos que ainda estavam abertos quando o disco foi salvo não são mais alcançáveis
+        disk.reclaim_unlinked();
+        disk.sync()?;
 
         println!("Done =)");
 
-        println!("\nTamanho do disco (kbytes): {}", memory_size_in_bytes / 1024);
-        println!("Tamanho do bloco de memória (kbytes): {}", block_size / 1024);
-        println!("Quantidade máxima de arquivos (Inode {} bytes): {}", inode_size, max_files);
+        println!("\nTamanho do disco (kbytes): {}", disk.image.super_block.image_size() / 1024);
+        println!("Tamanho do bloco de memória (kbytes): {}", disk.block_size / 1024);
+        println!("Quantidade máxima de arquivos: {}", disk.max_files);
 
-        Disk {
-            memory_blocks: memory_blocks.into_boxed_slice(),
+        Ok(disk)
+    }
+
+    /// Carrega o disco guardado no arquivo de imagem `image_path` exatamente como está, sem criar o diretório raiz nem
+    /// corrigir nada. Somente as transações que ficaram no journal são refeitas na imagem.
+    ///
+    /// Com `read_only`, a imagem é aberta somente para leitura: as transações do journal são refeitas só em memória e
+    /// as alterações feitas depois no disco também ficam só em memória, sem nada ser gravado no journal nem na imagem.
+    pub fn load(image_path: &Path, read_only: bool) -> io::Result<Disk> {
+        let image = Image::open(image_path, read_only)?;
+
+        // Transações gravadas no journal que não chegaram a ser aplicadas indicam que o FS não foi desmontado
+        // corretamente; elas são refeitas antes de ler o restante da imagem
+        let (mut journal, transactions) = Journal::open(&image, read_only)?;
+        let mut pending = Checkpoint::default();
+        if !transactions.is_empty() {
+            println!("Refazendo {} transações do journal{}...", transactions.len(), if read_only { " em memória" } else { "" });
+
+            if read_only {
+                for transaction in transactions {
+                    pending.merge(transaction);
+                }
+            } else {
+                for transaction in transactions.iter() {
+                    transaction.apply(&image)?;
+                }
+                image.sync()?;
+                journal.reset(&image)?;
+            }
+        }
+
+        let header = image.super_block;
+        let block_size = header.block_size as usize;
+        let max_files = header.inode_count as usize;
+        let memory_block_quantity = header.block_count as usize;
+
+        // Os Inodes das transações refeitas só em memória substituem os lidos da imagem. Os blocos são lidos direto de
+        // `pending`
+        let mut inodes = image.read_inodes()?;
+        for (index, inode) in pending.inodes.iter() {
+            inodes[*index] = Ok(inode.clone());
+        }
+
+        // Um Inode corrompido não impede o disco de ser carregado: somente ele fica inacessível
+        let mut corrupted_inodes: HashSet<usize> = HashSet::new();
+        let super_block: Vec<Option<Inode>> = inodes.into_iter()
+            .enumerate()
+            .map(|(index, inode)| inode.unwrap_or_else(|e| {
+                println!("Erro na tabela de Inodes! {}", e);
+                corrupted_inodes.insert(index);
+                None
+            }))
+            .collect();
+
+        let inode_bitmap_bytes = with_words(image.read_bitmap(true)?, &pending.inode_bitmap);
+        let mut inode_bitmap = Bitmap::from_bytes(&inode_bitmap_bytes, max_files);
+        for index in corrupted_inodes.iter() {
+            inode_bitmap.set(*index);
+        }
+        let block_bitmap_bytes = with_words(image.read_bitmap(false)?, &pending.block_bitmap);
+        let block_bitmap = Bitmap::from_bytes(&block_bitmap_bytes, memory_block_quantity);
+
+        Ok(Disk {
+            image,
+            journal,
+            pending,
+            unjournaled: false,
+            read_only,
             super_block: super_block.into_boxed_slice(),
+            dirty_inodes: HashSet::new(),
+            corrupted_inodes,
+            block_cache: RefCell::new(HashMap::new()),
+            dirty_blocks: HashMap::new(),
+            cache_capacity: (CACHE_SIZE / block_size).max(MIN_CACHE_BLOCKS),
+            inode_bitmap,
+            block_bitmap,
             max_files,
-            block_size,
-            root_path
-        }
+            block_size
+        })
     }
 
-    /// Procura pelo vetor `super_block` um espaço de memória vazio (com `None`) e retorna o número `ino` disponível, caso haja algum.
-    /// Por convenção, o número de inode `ino` é o número do indíce que ele ocupa no vetor `super_block` + 1.
-    pub fn find_ino_available(&self) -> Option<u64> {
-        for index in 0..self.super_block.len() - 1 {
-            if let Option::None = self.super_block[index] {
-                let ino = (index as u64) + 1;
-                return Option::Some(ino);
+    /// Geometria do disco, como está gravada no superbloco da imagem.
+    pub fn header(&self) -> &SuperBlock {
+        &self.image.super_block
+    }
+
+    /// `nlink` de cada posição do `super_block` de acordo com as referências dos diretórios: arquivos possuem uma
+    /// ligação para cada diretório que os referencia e diretórios possuem 2 (o "." e a entrada no pai) mais um ".." para
+    /// cada subdiretório.
+    fn expected_links(&self) -> Vec<u32> {
+        let mut links: Vec<u32> = self.super_block.iter()
+            .map(|inode| match inode {
+                Some(inode) if inode.attributes.kind == FileType::Directory => 2,
+                _ => 0
+            })
+            .collect();
+
+        for inode in self.super_block.iter().flatten() {
+            if inode.attributes.kind != FileType::Directory {
+                continue;
+            }
+
+            for entry in self.read_directory(inode.attributes.ino).unwrap_or_default() {
+                let child_index = entry.ino.wrapping_sub(1) as usize;
+                match self.super_block.get(child_index) {
+                    Some(Some(child)) if child.attributes.kind == FileType::Directory => {
+                        links[(inode.attributes.ino - 1) as usize] += 1;
+                    },
+                    Some(Some(_)) => links[child_index] += 1,
+                    _ => ()
+                }
             }
         }
 
-        Option::None
+        links
     }
 
-    /// Procura pelo vetor `memory_blocks` um espaço de memória vazio (com `None`) e retorna o índice do bloco, caso haja algum.
-    pub fn find_index_of_empty_memory_block(&self) -> Option<usize> {
-        for index in 0..self.memory_blocks.len() - 1 {
-            if let Option::None = self.memory_blocks[index].data {
-                return Option::Some(index);
+    /// Confere o checksum de todos os blocos de memória ocupados na imagem, registrando no log cada bloco corrompido e
+    /// o Inode que o usa. Os checksums dos Inodes já são conferidos ao carregar o disco. Retorna a quantidade de
+    /// blocos corrompidos.
+    pub fn scrub(&self) -> usize {
+        let mut owners: HashMap<usize, u64> = HashMap::new();
+        for inode in self.super_block.iter().flatten() {
+            for block_index in self.file_blocks(inode).unwrap_or_default() {
+                owners.insert(block_index, inode.attributes.ino);
+            }
+        }
+
+        let mut data = vec![0u8; self.block_size];
+        let mut corrupted = 0;
+
+        for block_index in (0..self.block_bitmap.capacity()).filter(|index| self.block_bitmap.is_set(*index)) {
+            if let Err(e) = self.image.read_block(block_index, &mut data) {
+                corrupted += 1;
+                match owners.get(&block_index) {
+                    Some(ino) => println!("Erro no bloco de memória {} do Inode {}! {}", block_index, ino, e),
+                    None => println!("Erro no bloco de memória {}! {}", block_index, e)
+                }
             }
         }
 
-        Option::None
+        corrupted
     }
 
-    /// Procura pelo vetor de `references` de um inode identificado pelo seu número `ino` o primeiro espaço vazio e retorna seu índice.
-    pub fn find_index_of_empty_reference_in_inode(&self, ino: u64) -> Option<usize> {
-        let index = (ino as usize) - 1;
-        match &self.super_block[index] {
-            Some(inode) => inode.references.iter().position(|r| r == &None),
-            None => panic!("Tentativa inválida de memória")
+    /// Libera os arquivos sem nenhuma ligação, junto com os seus blocos de memória. Eles foram removidos enquanto ainda
+    /// estavam abertos e o disco não foi desmontado depois que foram fechados. Inodes que não são alcançáveis a partir
+    /// do root por outros motivos não são tocados: recuperá-los é tarefa do fsck.
+    pub fn reclaim_unlinked(&mut self) {
+        let unlinked: Vec<u64> = self.super_block.iter()
+            .flatten()
+            .filter(|inode| inode.attributes.kind != FileType::Directory && inode.attributes.nlink == 0)
+            .map(|inode| inode.attributes.ino)
+            .collect();
+
+        for ino in unlinked {
+            println!("Liberando Inode {}, removido enquanto estava aberto", ino);
+            self.free_inode(ino);
+            self.commit_if_large();
         }
     }
 
-    /// Salva o `inode` no vetor de `super_block`. Caso o número `ino` de Inode já exista, o dado é sobrescrito.
-    pub fn write_inode(&mut self, inode: Inode) {
-        if mem::size_of_val(&inode) > self.block_size {
-            println!("Não foi possível salvar o inode: tamanho maior que o tamanho do bloco de memória");
-            return;
+    /// Soma uma ligação ao `nlink` do Inode `ino`.
+    pub fn increment_nlink(&mut self, ino: u64) {
+        if let Some(inode) = self.get_inode_as_mut(ino) {
+            inode.attributes.nlink += 1;
+        }
+    }
+
+    /// Remove uma ligação do `nlink` do Inode `ino` e retorna quantas ainda restam.
+    pub fn decrement_nlink(&mut self, ino: u64) -> u32 {
+        match self.get_inode_as_mut(ino) {
+            Some(inode) => {
+                inode.attributes.nlink = inode.attributes.nlink.saturating_sub(1);
+                inode.attributes.nlink
+            },
+            None => 0
         }
+    }
+
+    /// Procura no mapa de bits do `super_block` uma posição vazia e retorna o número `ino` disponível, caso haja algum.
+    /// Por convenção, o número de inode `ino` é o número do indíce que ele ocupa no vetor `super_block` + 1.
+    /// A posição só é marcada como ocupada quando o Inode é salvo com `write_inode`.
+    pub fn find_ino_available(&mut self) -> Option<u64> {
+        self.inode_bitmap.find_free().map(|index| (index as u64) + 1)
+    }
+
+    /// Procura no mapa de bits dos blocos de memória um bloco vazio e retorna o seu índice, caso haja algum.
+    /// O bloco só é marcado como ocupado quando recebe conteúdo com `write_content_as_bytes`.
+    pub fn find_index_of_empty_memory_block(&mut self) -> Option<usize> {
+        self.block_bitmap.find_free()
+    }
 
+    /// Tamanho de cada bloco de memória, em bytes.
+    pub fn get_block_size(&self) -> usize {
+        self.block_size
+    }
+
+    /// Quantidade total de blocos de memória do disco.
+    pub fn total_memory_blocks(&self) -> usize {
+        self.block_bitmap.capacity()
+    }
+
+    /// Quantidade de blocos de memória que ainda não guardam conteúdo de nenhum arquivo.
+    pub fn free_memory_blocks(&self) -> usize {
+        self.block_bitmap.free()
+    }
+
+    /// Quantidade total de Inodes que o `super_block` comporta.
+    pub fn total_inodes(&self) -> usize {
+        self.super_block.len()
+    }
+
+    /// Quantidade de posições vazias no `super_block`.
+    pub fn free_inodes(&self) -> usize {
+        self.inode_bitmap.free()
+    }
+
+    /// Mapa de bits de alocação dos Inodes, se `inodes`, ou dos blocos de memória.
+    pub fn bitmap(&self, inodes: bool) -> &Bitmap {
+        if inodes { &self.inode_bitmap } else { &self.block_bitmap }
+    }
+
+    /// Salva o `inode` no vetor de `super_block`. Caso o número `ino` de Inode já exista, o dado é sobrescrito.
+    pub fn write_inode(&mut self, inode: Inode) {
         let index = (inode.attributes.ino - 1) as usize;
         self.super_block[index] = Some(inode);
+        self.inode_bitmap.set(index);
+        self.dirty_inodes.insert(index);
+        self.corrupted_inodes.remove(&index);
     }
 
+    /// Libera o bloco de memória `index`. O conteúdo antigo continua na imagem até o bloco ser reutilizado.
     pub fn clear_memory_block(&mut self, index: usize) {
-        self.memory_blocks[index] = MemoryBlock { data: None };
+        self.dirty_blocks.remove(&index);
+        self.block_cache.borrow_mut().remove(&index);
+        self.block_bitmap.clear(index);
     }
 
     pub fn clear_inode(&mut self, ino: u64) {
         let index = (ino - 1) as usize;
         self.super_block[index] = None;
+        self.inode_bitmap.clear(index);
+        self.dirty_inodes.insert(index);
+        self.corrupted_inodes.remove(&index);
     }
 
-    /// Remove a referência do vetor de references de um Inode
-    pub fn clear_reference_in_inode(&mut self, ino: u64, ref_value: usize) {
-        let index = (ino - 1) as usize;
-        let inode: &mut Option<Inode> = &mut self.super_block[index];
-        
-        match inode {
-            Some(inode) => {
-                let reference_index: Option<usize> = inode.references.iter().position(|r| match r {
-                    Some(reference) => *reference == ref_value,
-                    None => false
-                });
-
-                match reference_index {
-                    Some(reference_index) => inode.references[reference_index] = None,
-                    None => panic!("fn clear_reference_in_inode: Referência não encontrada no Inode.")
+    /// Indica se o registro do Inode `ino` na imagem está corrompido, o que o torna inacessível.
+    pub fn is_inode_corrupted(&self, ino: u64) -> bool {
+        (ino as usize).checked_sub(1).is_some_and(|index| self.corrupted_inodes.contains(&index))
+    }
+
+    /// Erro de uma operação sobre o Inode `ino`, que não foi encontrado: `EIO` se o registro dele estiver corrompido e
+    /// `ENOENT` caso contrário.
+    pub fn inode_error(&self, ino: u64) -> c_int {
+        if self.is_inode_corrupted(ino) { EIO } else { ENOENT }
+    }
+
+    /// Profundidade do índice do diretório `ino`, ou `None` se ele ainda não possui o índice por nunca ter recebido
+    /// entradas. Retorna `ENOTDIR` se o Inode não for um diretório e `EIO` se o cabeçalho do índice estiver corrompido.
+    fn directory_depth(&self, ino: u64) -> Result<Option<u32>, c_int> {
+        let size = match self.get_inode(ino) {
+            Some(inode) if inode.attributes.kind == FileType::Directory => inode.attributes.size as usize,
+            Some(_) => return Err(ENOTDIR),
+            None => return Err(self.inode_error(ino))
+        };
+
+        if size == 0 {
+            return Ok(None);
+        }
+
+        let mut header = [0u8; DIRECTORY_HEADER_SIZE];
+        header.copy_from_slice(&self.read_content(ino, 0, DIRECTORY_HEADER_SIZE)?);
+        let depth = u64::from_le_bytes(header);
+
+        if depth > u64::from(MAX_DIRECTORY_DEPTH) || self.table_blocks(depth as u32) >= size / self.block_size {
+            println!("Índice do diretório {} corrompido: profundidade {} inválida", ino, depth);
+            return Err(EIO);
+        }
+
+        Ok(Some(depth as u32))
+    }
+
+    /// Quantidade de blocos lógicos ocupados pelo cabeçalho e pela tabela de um índice com profundidade `depth`.
+    fn table_blocks(&self, depth: u32) -> usize {
+        (DIRECTORY_HEADER_SIZE + (SLOT_SIZE << depth)).div_ceil(self.block_size)
+    }
+
+    /// Lê as posições `slots` da tabela do índice do diretório `ino`, que tem profundidade `depth`: o bloco lógico do
+    /// bucket de cada posição. Retorna `EIO` se alguma delas não apontar para um bucket do diretório.
+    fn read_directory_table(&self, ino: u64, depth: u32, slots: Range<usize>) -> Result<Vec<usize>, c_int> {
+        let blocks = self.get_inode(ino).ok_or(ENOENT)?.attributes.size as usize / self.block_size;
+        let first = slots.start;
+        let content = self.read_content(ino, (DIRECTORY_HEADER_SIZE + slots.start * SLOT_SIZE) as u64, slots.len() * SLOT_SIZE)?;
+
+        content.chunks(SLOT_SIZE).enumerate().map(|(position, bytes)| {
+            let mut slot = [0u8; SLOT_SIZE];
+            slot.copy_from_slice(bytes);
+            let logical = u64::from_le_bytes(slot) as usize;
+
+            if logical < self.table_blocks(depth) || logical >= blocks {
+                println!("Índice do diretório {} corrompido: a posição {} aponta para o bloco {}", ino, first + position, logical);
+                return Err(EIO);
+            }
+
+            Ok(logical)
+        }).collect()
+    }
+
+    /// Grava o cabeçalho e a tabela `table` inteira do índice do diretório `ino`, com profundidade `depth`.
+    fn write_directory_table(&mut self, ino: u64, depth: u32, table: &[usize]) -> Result<(), c_int> {
+        let mut content = Vec::with_capacity(self.table_blocks(depth) * self.block_size);
+        content.extend_from_slice(&u64::from(depth).to_le_bytes());
+        for logical in table {
+            content.extend_from_slice(&(*logical as u64).to_le_bytes());
+        }
+
+        // O último bloco da tabela é gravado inteiro para que os buckets comecem sempre no início de um bloco
+        content.resize(self.table_blocks(depth) * self.block_size, 0);
+        self.write_directory_content(ino, 0, &content)
+    }
+
+    /// Lê o bucket guardado no bloco lógico `logical` do diretório `ino`.
+    fn read_bucket(&self, ino: u64, logical: usize) -> Result<Bucket, c_int> {
+        let references = self.get_inode(ino).ok_or(ENOENT)?.references;
+        let block_index = match self.file_block(&references, logical)? {
+            Some(block_index) => block_index,
+            None => {
+                println!("Índice do diretório {} corrompido: o bucket do bloco {} não existe", ino, logical);
+                return Err(EIO);
+            }
+        };
+
+        // O bucket é desserializado direto do bloco de memória; os bytes após as entradas são ignorados
+        match self.with_memory_block(block_index, |data| deserialize(data)).ok_or(EIO)? {
+            Ok(bucket) => Ok(bucket),
+            Err(e) => {
+                println!("Erro lendo as entradas do diretório {}! {}", ino, e);
+                Err(EIO)
+            }
+        }
+    }
+
+    /// Grava o bucket `bucket` no bloco lógico `logical` do diretório `ino`, alocando o bloco se necessário.
+    fn write_bucket(&mut self, ino: u64, logical: usize, bucket: &Bucket) -> Result<(), c_int> {
+        let mut content = match serialize(bucket) {
+            Ok(content) => content,
+            Err(e) => {
+                println!("Erro ao serializar as entradas do diretório {}! {}", ino, e);
+                return Err(EIO);
+            }
+        };
+
+        // O bloco é gravado inteiro para que o tamanho do diretório seja sempre um múltiplo do bloco
+        content.resize(self.block_size, 0);
+        self.write_directory_content(ino, (logical * self.block_size) as u64, &content)
+    }
+
+    /// Verifica se o bucket `bucket` serializado cabe em um bloco de memória.
+    fn fits_in_bucket(&self, bucket: &Bucket) -> bool {
+        serialized_size(bucket).is_ok_and(|size| size as usize <= self.block_size)
+    }
+
+    /// Confere se há espaço para os blocos lógicos `logicals` do diretório `ino` e se a transação comporta os
+    /// `changed` blocos que a alteração do índice grava, para que ele nunca fique pela metade. Retorna `ENOSPC` se o
+    /// disco não tiver blocos livres suficientes, se o diretório já tiver o tamanho máximo de um arquivo ou se a
+    /// alteração não couber no journal.
+    fn reserve_directory_blocks(&self, ino: u64, logicals: Range<usize>, changed: usize) -> Result<(), c_int> {
+        let references = self.get_inode(ino).ok_or(ENOENT)?.references;
+
+        if logicals.end > self.max_file_blocks() || self.missing_blocks(&references, logicals)? > self.free_memory_blocks() {
+            println!("Não há espaço para aumentar o diretório {}!", ino);
+            return Err(ENOSPC);
+        }
+
+        if changed > self.transaction_room() {
+            println!("Não é possível aumentar o diretório {}: a alteração do índice não cabe no journal!", ino);
+            return Err(ENOSPC);
+        }
+
+        Ok(())
+    }
+
+    /// Escreve `data` no diretório `ino` a partir de `offset`. O espaço e a transação já foram conferidos com
+    /// `reserve_directory_blocks`, então uma escrita incompleta indica que o índice ficou inconsistente.
+    fn write_directory_content(&mut self, ino: u64, offset: u64, data: &[u8]) -> Result<(), c_int> {
+        match self.write_content(ino, offset, data)? {
+            written if written == data.len() => Ok(()),
+            _ => {
+                println!("Escrita incompleta no índice do diretório {}!", ino);
+                Err(EIO)
+            }
+        }
+    }
+
+    /// Encontra o bucket do diretório `ino` onde fica o hash `hash`, lendo somente a posição da tabela e o bloco do
+    /// bucket. Retorna `None` se o diretório ainda não possui o índice.
+    fn locate_bucket(&self, ino: u64, hash: u64) -> Result<Option<BucketLocation>, c_int> {
+        let table_depth = match self.directory_depth(ino)? {
+            Some(depth) => depth,
+            None => return Ok(None)
+        };
+
+        let slot = slot_of(hash, table_depth);
+        let logical = self.read_directory_table(ino, table_depth, slot..slot + 1)?[0];
+        let bucket = self.read_bucket(ino, logical)?;
+
+        if bucket.depth > table_depth {
+            println!("Índice do diretório {} corrompido: bucket do bloco {} mais profundo que a tabela", ino, logical);
+            return Err(EIO);
+        }
+
+        Ok(Some(BucketLocation { table_depth, slot, logical, bucket }))
+    }
+
+    /// Dobra a tabela do índice do diretório `ino`, que tem profundidade `depth`, sem alterar nenhum bucket: cada
+    /// posição se divide em duas que apontam para o mesmo bucket. Os buckets que ocupam os blocos para onde a tabela
+    /// cresce são movidos para o fim do diretório.
+    fn grow_directory_table(&mut self, ino: u64, depth: u32) -> Result<(), c_int> {
+        if depth >= MAX_DIRECTORY_DEPTH {
+            println!("Não é possível criar mais arquivos no diretório {}: índice cheio!", ino);
+            return Err(ENOSPC);
+        }
+
+        let blocks = self.get_inode(ino).ok_or(ENOENT)?.attributes.size as usize / self.block_size;
+        let new_table_blocks = self.table_blocks(depth + 1);
+        let moved = self.table_blocks(depth)..new_table_blocks.min(blocks);
+        let destination = blocks.max(new_table_blocks);
+
+        self.reserve_directory_blocks(ino, blocks.min(new_table_blocks)..destination + moved.len(), new_table_blocks + moved.len())?;
+
+        let table = self.read_directory_table(ino, depth, 0..1 << depth)?;
+        for (position, logical) in moved.clone().enumerate() {
+            let content = self.read_content(ino, (logical * self.block_size) as u64, self.block_size)?;
+            self.write_directory_content(ino, ((destination + position) * self.block_size) as u64, &content)?;
+        }
+
+        let table: Vec<usize> = table.into_iter()
+            .map(|logical| if moved.contains(&logical) { destination + logical - moved.start } else { logical })
+            .flat_map(|logical| [logical, logical])
+            .collect();
+
+        self.write_directory_table(ino, depth + 1, &table)
+    }
+
+    /// Divide o bucket `location` do diretório `ino` em dois, um com os hashes cujo próximo bit é 0 e outro com os
+    /// hashes cujo próximo bit é 1, que vai para um bloco novo no fim do diretório. Somente as posições da tabela que
+    /// apontavam para o bucket são alteradas.
+    fn split_bucket(&mut self, ino: u64, location: BucketLocation) -> Result<(), c_int> {
+        let BucketLocation { table_depth, slot, logical, bucket } = location;
+        // Os dois buckets e os blocos da tabela com as posições alteradas
+        let span = 1usize << (table_depth - bucket.depth);
+        let changed = 2 + (span / 2 * SLOT_SIZE).div_ceil(self.block_size) + 1;
+        let new_logical = self.get_inode(ino).ok_or(ENOENT)?.attributes.size as usize / self.block_size;
+        self.reserve_directory_blocks(ino, new_logical..new_logical + 1, changed)?;
+
+        let depth = bucket.depth + 1;
+        let (low, high): (Vec<DirectoryEntry>, Vec<DirectoryEntry>) = bucket.entries.into_iter()
+            .partition(|entry| (name_hash(&entry.name) >> (64 - depth)) & 1 == 0);
+
+        self.write_bucket(ino, new_logical, &Bucket { depth, entries: high })?;
+        self.write_bucket(ino, logical, &Bucket { depth, entries: low })?;
+
+        // As posições do bucket antigo formam um intervalo alinhado; a segunda metade passa a ser do bucket novo
+        let first = (slot & !(span - 1)) + span / 2;
+        let slots: Vec<u8> = (0..span / 2).flat_map(|_| (new_logical as u64).to_le_bytes()).collect();
+        self.write_directory_content(ino, (DIRECTORY_HEADER_SIZE + first * SLOT_SIZE) as u64, &slots)
+    }
+
+    /// Lê todas as entradas do diretório `ino`, na ordem dos hashes dos nomes.
+    pub fn read_directory(&self, ino: u64) -> Option<Vec<DirectoryEntry>> {
+        let mut entries: Vec<DirectoryEntry> = Vec::new();
+        let mut next = Some(0);
+
+        while let Some(min_hash) = next {
+            let (bucket_entries, following) = self.read_directory_bucket(ino, min_hash)?;
+            entries.extend(bucket_entries);
+            next = following;
+        }
+
+        Some(entries)
+    }
+
+    /// Lê as entradas do bucket do diretório `ino` onde fica o hash `min_hash` cujo hash do nome é maior ou igual a
+    /// `min_hash`, na ordem dos hashes, junto com o primeiro hash do bucket seguinte (ou `None` se esse for o último).
+    /// Somente um bucket é lido, o que permite listar o diretório aos poucos e continuar uma listagem interrompida sem
+    /// percorrer o diretório desde o começo.
+    pub fn read_directory_bucket(&self, ino: u64, min_hash: u64) -> Option<(Vec<DirectoryEntry>, Option<u64>)> {
+        let location = match self.locate_bucket(ino, min_hash).ok()? {
+            Some(location) => location,
+            None => return Some((Vec::new(), None))
+        };
+
+        // O bucket ocupa um intervalo alinhado de posições da tabela; o seguinte começa logo depois dele
+        let span = 1usize << (location.table_depth - location.bucket.depth);
+        let next_slot = (location.slot | (span - 1)) + 1;
+        let next = if next_slot < 1 << location.table_depth {
+            Some((next_slot as u64) << (64 - location.table_depth))
+        } else {
+            None
+        };
+
+        let entries = location.bucket.entries.into_iter()
+            .filter(|entry| name_hash(&entry.name) >= min_hash)
+            .collect();
+
+        Some((entries, next))
+    }
+
+    /// Procura a entrada `name` no diretório `parent`, lendo somente o bucket correspondente ao hash do nome.
+    pub fn find_directory_entry(&self, parent: u64, name: &OsStr) -> Option<DirectoryEntry> {
+        self.locate_bucket(parent, name_hash(name.as_bytes())).ok()??
+            .bucket
+            .entries
+            .into_iter()
+            .find(|entry| entry.name == name.as_bytes())
+    }
+
+    /// Adiciona ao diretório `parent` uma entrada chamada `name` apontando para o Inode `ino` do tipo `kind`.
+    /// Quando o bucket do nome não comporta a nova entrada, somente ele é dividido, dobrando antes a tabela do índice
+    /// se o bucket já usa todos os bits dela. O diretório só é limitado pelo espaço livre e pelo tamanho máximo de um
+    /// arquivo.
+    pub fn add_directory_entry(&mut self, parent: u64, name: &OsStr, ino: u64, kind: FileType) -> Result<(), c_int> {
+        if name.len() > NAME_MAX {
+            return Err(ENAMETOOLONG);
+        }
+
+        let hash = name_hash(name.as_bytes());
+        let entry = DirectoryEntry { name: name.as_bytes().to_vec(), ino, kind };
+
+        loop {
+            let location = match self.locate_bucket(parent, hash)? {
+                Some(location) => location,
+                None => {
+                    // Primeira entrada: o índice começa com a tabela de uma posição e um único bucket
+                    let logical = self.table_blocks(0);
+                    self.reserve_directory_blocks(parent, 0..logical + 1, logical + 1)?;
+                    self.write_directory_table(parent, 0, &[logical])?;
+                    return self.write_bucket(parent, logical, &Bucket { depth: 0, entries: vec![entry] });
                 }
-            },
-            None => panic!("fn clear_reference_in_inode: Tentativa de remoção de referência em um Inode vazio.")
+            };
+
+            let mut bucket = Bucket { depth: location.bucket.depth, entries: location.bucket.entries.clone() };
+            let position = bucket.entries.iter().position(|entry| name_hash(&entry.name) > hash).unwrap_or(bucket.entries.len());
+            bucket.entries.insert(position, entry.clone());
+
+            if self.fits_in_bucket(&bucket) {
+                return self.write_bucket(parent, location.logical, &bucket);
+            }
+
+            if location.bucket.depth == location.table_depth {
+                self.grow_directory_table(parent, location.table_depth)?;
+            } else {
+                self.split_bucket(parent, location)?;
+            }
+        }
+    }
+
+    /// Remove a entrada `name` do diretório `parent` e retorna o número do Inode para o qual ela apontava. Os buckets
+    /// nunca são juntados, então o diretório não diminui.
+    pub fn remove_directory_entry(&mut self, parent: u64, name: &OsStr) -> Option<u64> {
+        let mut location = self.locate_bucket(parent, name_hash(name.as_bytes())).ok()??;
+        let position = location.bucket.entries.iter().position(|entry| entry.name == name.as_bytes())?;
+        let entry = location.bucket.entries.remove(position);
+
+        // Um bucket menor sempre cabe no bloco que ele já possui
+        self.write_bucket(parent, location.logical, &location.bucket).ok()?;
+        Some(entry.ino)
+    }
+
+    /// Faz a entrada `name` do diretório `parent` apontar para o Inode `ino` do tipo `kind`, mantendo o nome.
+    pub fn replace_directory_entry(&mut self, parent: u64, name: &OsStr, ino: u64, kind: FileType) {
+        if let Ok(Some(mut location)) = self.locate_bucket(parent, name_hash(name.as_bytes())) {
+            if let Some(entry) = location.bucket.entries.iter_mut().find(|entry| entry.name == name.as_bytes()) {
+                entry.ino = ino;
+                entry.kind = kind;
+                // O bucket serializado tem o mesmo tamanho, então não há alocação de novos blocos
+                let _ = self.write_bucket(parent, location.logical, &location.bucket);
+            }
         }
     }
 
-    /// Retorna a referência mutável de memória do `Inode`.
+    /// Retorna a referência mutável de memória do `Inode`, que passa a ser gravado novamente na imagem.
     pub fn get_inode_as_mut(&mut self, ino: u64) -> Option<&mut Inode> {
         let index = (ino as usize) - 1;
+        self.dirty_inodes.insert(index);
         match &mut self.super_block[index] {
             Some(inode) => Some(inode),
             None => None
@@ -231,47 +829,75 @@ impl Disk {
 
     /// Retorna o `Inode` especificado pelo seu número `ino`.
     pub fn get_inode(&self, ino: u64) -> Option<&Inode> {
-        let index = (ino as usize) - 1;
-        match &self.super_block[index] {
+        let index = (ino as usize).checked_sub(1)?;
+        match self.super_block.get(index)? {
             Some(inode) => Some(inode),
             None => None
         }
     }
     
-    /// Procura o Inode pelo nome dentro de um vetor de referências do Inode pai.
-    pub fn find_inode_in_references_by_name(&self, parent_inode_ino: u64, name: &str) -> Option<&Inode> {
-        let index = (parent_inode_ino as usize) - 1;
-        let parent_inode = &self.super_block[index];
-
-        match parent_inode {
-            Some(parent_inode) => {
-                // Procura pelo vetor de references do Inode
-                for ino_ref in parent_inode.references.iter() {
-                    // Se houver algum dado dentro de ino_ref, então entra no bloco e pega esse conteúdo
-                    if let Some(ino) = ino_ref {
-                        let index: usize = (ino.clone() as usize) - 1;
-                        let inode_ref = &self.super_block[index];
-
-                        match inode_ref {
-                            Some(inode) => {
-                                let name_from_inode: String = inode.name.iter().collect::<String>();
-                                let name_from_inode: &str = name_from_inode.as_str().trim_matches(char::from(0)); // Remoção de caracteres '\0'
-                                let name = name.trim();
-                                println!("    - lookup(name={:?}, name_from_inode={:?}, equals={})", name, name_from_inode, name_from_inode == name);
-                                
-                                if name_from_inode == name {
-                                    return Some(inode);
-                                }
-                            },
-                            None => panic!("fn get_inode_by_name: Inode reference não encontrado")
-                        }
-                    }
-                }
-            },
-            None => panic!("fn get_inode_by_name: Inode parent não encontrado")
+    /// Procura o Inode pelo nome entre as entradas do diretório `parent_inode_ino`. Retorna `EIO` se a entrada existir
+    /// mas o Inode para o qual ela aponta não puder ser lido.
+    pub fn find_inode_in_references_by_name(&self, parent_inode_ino: u64, name: &OsStr) -> Result<Option<&Inode>, c_int> {
+        if self.get_inode(parent_inode_ino).is_none() {
+            return Err(self.inode_error(parent_inode_ino));
+        }
+
+        let entry = match self.find_directory_entry(parent_inode_ino, name) {
+            Some(entry) => entry,
+            None => return Ok(None)
+        };
+
+        match self.get_inode(entry.ino) {
+            Some(inode) => Ok(Some(inode)),
+            None => {
+                println!("Entrada {:?} do diretório {} aponta para o Inode {}, que não pode ser lido", name, parent_inode_ino, entry.ino);
+                Err(EIO)
+            }
+        }
+    }
+
+    /// Remove o Inode `ino` do `super_block` e libera os blocos de memória com o seu conteúdo.
+    pub fn free_inode(&mut self, ino: u64) {
+        let blocks = match self.get_inode(ino) {
+            Some(inode) => self.file_blocks(inode).unwrap_or_else(|_| {
+                // Sem os blocos de ponteiros, somente os blocos apontados direto pelo Inode são liberados
+                println!("Erro ao ler os blocos de ponteiros do Inode {}!", ino);
+                inode.references.iter().flatten().copied().collect()
+            }),
+            None => return
+        };
+
+        for memory_block_index in blocks {
+            self.clear_memory_block(memory_block_index);
+        }
+
+        self.clear_inode(ino);
+    }
+
+    /// Verifica se o diretório `ino` não possui nenhuma entrada.
+    pub fn is_directory_empty(&self, ino: u64) -> bool {
+        self.read_directory(ino).is_none_or(|entries| entries.is_empty())
+    }
+
+    /// Verifica se o diretório `ino` está dentro da árvore de diretórios que começa em `ancestor` (inclusive o próprio),
+    /// subindo pelo `parent` de cada diretório até o root.
+    pub fn is_descendant(&self, ancestor: u64, ino: u64) -> bool {
+        let mut current = ino;
+
+        // Um `parent` corrompido poderia formar um ciclo, então o caminho é limitado pela quantidade de Inodes
+        for _ in 0..self.super_block.len() {
+            if current == ancestor {
+                return true;
+            }
+
+            match self.get_inode(current) {
+                Some(inode) if current != 1 => current = inode.parent,
+                _ => return false
+            }
         }
 
-        return None;
+        false
     }
 
     /// Retorna o vetor de references do Inode
@@ -283,97 +909,1092 @@ impl Disk {
         }
     }
 
-    /// Recupera o conteúdo de um bloco de memória convertido para `str`
-    pub fn get_content(&self, block_index: usize) -> Option<&str> {
-        let data = self.get_content_as_bytes(block_index);
-        
-        match &data {
-            Some(data) => {
-                Option::Some(str::from_utf8(&data).unwrap())
-            },
-            None => None
+    /// Executa `f` sobre o conteúdo do bloco de memória `block_index`, que tem sempre o tamanho de um bloco. O bloco
+    /// é lido da imagem somente se não estiver em memória, e então fica no cache para as próximas leituras.
+    /// Retorna `None` se a leitura da imagem falhar.
+    pub fn with_memory_block<R, F: FnOnce(&[u8]) -> R>(&self, block_index: usize, f: F) -> Option<R> {
+        if let Some(data) = self.dirty_blocks.get(&block_index).or_else(|| self.pending.blocks.get(&block_index)) {
+            return Some(f(data));
+        }
+
+        if let Some(data) = self.block_cache.borrow().get(&block_index) {
+            return Some(f(data));
         }
+
+        let mut data = vec![0u8; self.block_size];
+        if let Err(e) = self.image.read_block(block_index, &mut data) {
+            println!("Erro ao ler o bloco de memória {}! {}", block_index, e);
+            return None;
+        }
+
+        let result = f(&data);
+        self.cache_block(block_index, data.into_boxed_slice());
+        Some(result)
     }
 
-    /// Recupera um array de bytes borrowed de um bloco especificado.
-    ///
-    /// # Exemplos
-    ///
-    /// ```.
-    /// let disk = Disk::new(args);
-    /// let content: [u8] = disk.get_content_as_bytes(1);
-    /// ```
-    pub fn get_content_as_bytes(&self, block_index: usize) -> &Option<Box<[u8]>> {
-        let memory_block = &self.memory_blocks[block_index];
-        return &memory_block.data;
+    /// Guarda um bloco lido da imagem no cache, descartando outro bloco qualquer caso o cache esteja cheio.
+    fn cache_block(&self, block_index: usize, data: Box<[u8]>) {
+        let mut cache = self.block_cache.borrow_mut();
+        if cache.len() >= self.cache_capacity {
+            if let Some(evicted) = cache.keys().next().copied() {
+                cache.remove(&evicted);
+            }
+        }
+
+        cache.insert(block_index, data);
+    }
+
+    /// Retira o conteúdo do bloco de memória `block_index` para que seja alterado e devolvido com
+    /// `write_content_as_bytes`.
+    fn take_memory_block(&mut self, block_index: usize) -> Result<Vec<u8>, c_int> {
+        if let Some(data) = self.dirty_blocks.remove(&block_index) {
+            return Ok(data.into_vec());
+        }
+
+        // A cópia da última transação continua guardada até ser aplicada na imagem
+        if let Some(data) = self.pending.blocks.get(&block_index) {
+            return Ok(data.to_vec());
+        }
+
+        if let Some(data) = self.block_cache.borrow_mut().remove(&block_index) {
+            return Ok(data.into_vec());
+        }
+
+        let mut data = vec![0u8; self.block_size];
+        match self.image.read_block(block_index, &mut data) {
+            Ok(()) => Ok(data),
+            Err(e) => {
+                println!("Erro ao ler o bloco de memória {}! {}", block_index, e);
+                Err(EIO)
+            }
+        }
     }
 
     /// Escreve dados em bytes em um bloco de memória
     ///
     ///  # Exemplos
     /// 
-    /// ```
+    /// ```ignore
     /// let content: Box<[u8]> = Box::from(content.as_bytes());
     /// let disk: Disk = Disk::new(args);
     /// disk.write_content_as_bytes(1, content);
     /// ```
     /// 
-    /// Somente é gravado se for um local de memória válido
+    /// Somente é gravado se for um local de memória válido. Conteúdos menores que um bloco são completados com zeros.
+    /// O bloco fica em memória até a próxima transação, gravada pelo commit ao fim da operação.
     pub fn write_content_as_bytes(&mut self, block_index: usize, content: Box<[u8]>) {
         if content.len() > self.block_size {
             panic!("Não foi possível salvar o conteúdo do arquivo, pois excede o tamanho do bloco de memória {}", self.block_size);
         }
 
-        let memory_block = MemoryBlock { data: Some(content) };
-        self.memory_blocks[block_index] = memory_block;
+        let mut content = content.into_vec();
+        content.resize(self.block_size, 0);
+
+        self.block_cache.borrow_mut().remove(&block_index);
+        self.dirty_blocks.insert(block_index, content.into_boxed_slice());
+        self.block_bitmap.set(block_index);
     }
 
-    /// Escreve uma referência no vetor de references de um Inode de número ino
-    pub fn write_reference_in_inode(&mut self, ino: u64, ref_index: usize, ref_content: usize) {
-        let index = (ino as usize) - 1;
-        match &mut self.super_block[index] {
-            Some(inode) => {
-                inode.references[ref_index] = Some(ref_content);
-            },
-            None => panic!("fn write_reference_in_inode: Inode não encontrado!")
+    /// Converte uma quantidade de blocos de memória para o número de setores de 512 bytes esperado em `FileAttr.blocks`.
+    pub fn blocks_to_sectors(&self, block_quantity: usize) -> u64 {
+        let sectors_per_block = (self.block_size as u64).div_ceil(512);
+        block_quantity as u64 * sectors_per_block
+    }
+
+    /// Lê até `size` bytes do conteúdo do arquivo `ino` a partir de `offset`, percorrendo os blocos de memória
+    /// listados no vetor de references do Inode. Leituras além do fim do arquivo retornam menos bytes (ou nenhum).
+    /// Retorna `EIO` se algum bloco não puder ser lido da imagem ou estiver corrompido.
+    pub fn read_content(&self, ino: u64, offset: u64, size: usize) -> Result<Vec<u8>, c_int> {
+        let inode = self.get_inode(ino).ok_or_else(|| self.inode_error(ino))?;
+        let file_size = inode.attributes.size;
+
+        if offset >= file_size {
+            return Ok(Vec::new());
         }
+
+        let end = file_size.min(offset + size as u64) as usize;
+        let mut position = offset as usize;
+        let mut content: Vec<u8> = Vec::with_capacity(end - position);
+
+        while position < end {
+            let reference_index = position / self.block_size;
+            let block_offset = position % self.block_size;
+            let length = (self.block_size - block_offset).min(end - position);
+
+            match self.file_block(&inode.references, reference_index)? {
+                Some(block_index) => {
+                    self.with_memory_block(block_index, |data| {
+                        content.extend_from_slice(&data[block_offset..block_offset + length]);
+                    }).ok_or(EIO)?;
+                },
+                None => content.resize(content.len() + length, 0)
+            }
+
+            position += length;
+        }
+
+        Ok(content)
     }
 
-    pub fn write_to_disk(&mut self) {
-        match serialize(&self.super_block) {
-            Err(e) => {
-                print!("Erro ao tentar escrever para arquivo de inodes! {}", e);
-                return;
-            },
-            Ok(v) => {
-                let inode_file = format!("{}/.inode.risos", &self.root_path);
-                let mut inode_file = OpenOptions::new().write(true).open(inode_file).unwrap();
-                match inode_file.write(&v) {
-                    Err(e) => {
-                        print!("Erro ao tentar escrever para arquivo de inodes! {}", e);
-                        return;
+    /// Escreve `data` no arquivo `ino` a partir de `offset`, preservando o conteúdo já existente fora do intervalo escrito.
+    /// Somente os blocos de memória do intervalo escrito são alocados: o espaço entre o fim anterior do arquivo e
+    /// `offset` fica como um buraco, lido como zeros. Retorna a quantidade de bytes escritos ou o código de erro
+    /// correspondente.
+    ///
+    /// Somente os blocos que contêm o intervalo escrito são lidos e alterados: blocos novos já começam zerados e os bytes
+    /// após o fim do arquivo no seu último bloco são sempre zero.
+    ///
+    /// Toda a escrita precisa caber em uma única transação do journal. Se ela alterar blocos demais, somente o começo é
+    /// escrito e a quantidade menor de bytes é retornada; o restante deve ser escrito por outra operação.
+    pub fn write_content(&mut self, ino: u64, offset: u64, data: &[u8]) -> Result<usize, c_int> {
+        let (file_size, mut references) = match self.get_inode(ino) {
+            Some(inode) => (inode.attributes.size, inode.references),
+            None => return Err(self.inode_error(ino))
+        };
+
+        if data.is_empty() {
+            return Ok(0);
+        }
+
+        let first_block = offset as usize / self.block_size;
+        if (offset as usize + data.len()).div_ceil(self.block_size) > self.max_file_blocks() {
+            return Err(EFBIG);
+        }
+
+        let room = self.transaction_room();
+        if room == 0 {
+            println!("Não é possível escrever no Inode {}: a transação não cabe no journal!", ino);
+            return Err(ENOSPC);
+        }
+
+        // A escrita termina no último bloco que ainda cabe na transação
+        let end = (offset as usize + data.len()).min((first_block + room) * self.block_size);
+        let data = &data[..end - offset as usize];
+        let new_size = file_size.max(end as u64);
+        let last_block = end.div_ceil(self.block_size);
+
+        // Os blocos que faltam no intervalo, junto com os blocos de ponteiros, são contados antes de alocar, para que a
+        // escrita não fique pela metade caso o disco esteja cheio
+        if self.missing_blocks(&references, first_block..last_block)? > self.free_memory_blocks() {
+            return Err(ENOSPC);
+        }
+
+        let free_blocks = self.free_memory_blocks();
+        let written: Result<(), c_int> = (first_block..last_block).try_for_each(|logical| {
+            let block_start = logical * self.block_size;
+            let block_index = self.allocate_file_block(&mut references, logical)?;
+            let mut block_data = self.take_memory_block(block_index)?;
+
+            // Intersecção entre o intervalo escrito e o intervalo coberto pelo bloco
+            let write_start = (offset as usize).max(block_start);
+            let write_end = end.min(block_start + self.block_size);
+            block_data[write_start - block_start..write_end - block_start]
+                .copy_from_slice(&data[write_start - offset as usize..write_end - offset as usize]);
+
+            self.write_content_as_bytes(block_index, block_data.into_boxed_slice());
+            Ok(())
+        });
+
+        // Mesmo que a escrita falhe, os blocos já alocados ficam no Inode para não serem perdidos
+        let allocated = self.blocks_to_sectors(free_blocks - self.free_memory_blocks());
+        let inode = self.get_inode_as_mut(ino).unwrap();
+        inode.references = references;
+        inode.attributes.blocks += allocated;
+        written?;
+
+        inode.attributes.size = new_size;
+        Ok(data.len())
+    }
+
+    /// Altera o tamanho do arquivo `ino` para `new_size` bytes. Ao diminuir, os blocos de memória após o novo fim
+    /// são liberados e o último bloco é cortado; ao aumentar, nenhum bloco é alocado e o espaço novo fica como um
+    /// buraco, lido como zeros.
+    pub fn resize_content(&mut self, ino: u64, new_size: u64) -> Result<(), c_int> {
+        let (file_size, mut references) = match self.get_inode(ino) {
+            Some(inode) => (inode.attributes.size, inode.references),
+            None => return Err(self.inode_error(ino))
+        };
+
+        if new_size >= file_size {
+            if (new_size as usize).div_ceil(self.block_size) > self.max_file_blocks() {
+                return Err(EFBIG);
+            }
+
+            self.get_inode_as_mut(ino).unwrap().attributes.size = new_size;
+            return Ok(());
+        }
+
+        let new_size = new_size as usize;
+        let block_quantity = new_size.div_ceil(self.block_size);
+
+        // O último bloco mantido pode conter bytes após o novo fim, que não podem reaparecer caso o arquivo cresça
+        if block_quantity > 0 {
+            if let Some(block_index) = self.file_block(&references, block_quantity - 1)? {
+                let block_length = new_size - (block_quantity - 1) * self.block_size;
+                let mut data = self.take_memory_block(block_index)?;
+                data.truncate(block_length);
+                self.write_content_as_bytes(block_index, data.into_boxed_slice());
+            }
+        }
+
+        let free_blocks = self.free_memory_blocks();
+        self.free_file_blocks(&mut references, block_quantity)?;
+
+        let freed = self.blocks_to_sectors(self.free_memory_blocks() - free_blocks);
+        let inode = self.get_inode_as_mut(ino).unwrap();
+        inode.references = references;
+        inode.attributes.size = new_size as u64;
+        inode.attributes.blocks = inode.attributes.blocks.saturating_sub(freed);
+
+        Ok(())
+    }
+
+    /// Quantidade de ponteiros em um bloco de ponteiros.
+    fn pointers_per_block(&self) -> usize {
+        self.block_size / POINTER_SIZE
+    }
+
+    /// Maior quantidade de blocos com conteúdo que um arquivo pode ter.
+    fn max_file_blocks(&self) -> usize {
+        let pointers = self.pointers_per_block();
+        DIRECT_REFERENCES + pointers + pointers * pointers
+    }
+
+    /// Onde fica o bloco lógico `logical` de um arquivo, ou `None` se o arquivo não comporta tantos blocos.
+    fn block_path(&self, logical: usize) -> Option<BlockPath> {
+        let pointers = self.pointers_per_block();
+
+        if logical < DIRECT_REFERENCES {
+            return Some(BlockPath::Direct(logical));
+        }
+
+        let logical = logical - DIRECT_REFERENCES;
+        if logical < pointers {
+            return Some(BlockPath::Single(logical));
+        }
+
+        let logical = logical - pointers;
+        if logical < pointers * pointers {
+            Some(BlockPath::Double(logical / pointers, logical % pointers))
+        } else {
+            None
+        }
+    }
+
+    /// Quantidade de blocos de memória, inclusive os de ponteiros, que precisam ser alocados para que os blocos lógicos
+    /// `logicals` do arquivo com as references `references` existam.
+    fn missing_blocks(&self, references: &[Option<usize>; 128], logicals: Range<usize>) -> Result<usize, c_int> {
+        let mut missing = 0;
+        // Blocos de ponteiros que faltam: o indireto simples, o duplamente indireto e cada posição dele
+        let mut missing_pointers: HashSet<Option<usize>> = HashSet::new();
+
+        for logical in logicals {
+            if self.file_block(references, logical)?.is_some() {
+                continue;
+            }
+
+            missing += 1;
+            match self.block_path(logical).ok_or(EFBIG)? {
+                BlockPath::Direct(_) => (),
+                BlockPath::Single(_) => {
+                    if references[SINGLE_INDIRECT].is_none() {
+                        missing_pointers.insert(None);
+                    }
+                },
+                BlockPath::Double(outer, _) => match references[DOUBLE_INDIRECT] {
+                    Some(double) => {
+                        if self.read_pointer(double, outer)?.is_none() {
+                            missing_pointers.insert(Some(outer));
+                        }
                     },
-                    Ok(v) => v,
-                };
+                    None => {
+                        missing_pointers.insert(Some(self.pointers_per_block()));
+                        missing_pointers.insert(Some(outer));
+                    }
+                }
+            }
+        }
+
+        Ok(missing + missing_pointers.len())
+    }
+
+    /// Lê os ponteiros das posições `slots` do bloco de ponteiros `block_index`, ignorando os zerados.
+    fn read_pointers(&self, block_index: usize, slots: Range<usize>) -> Result<Vec<usize>, c_int> {
+        self.with_memory_block(block_index, |data| {
+            slots.filter_map(|slot| {
+                let mut bytes = [0u8; POINTER_SIZE];
+                bytes.copy_from_slice(&data[slot * POINTER_SIZE..(slot + 1) * POINTER_SIZE]);
+                (u64::from_le_bytes(bytes) as usize).checked_sub(1)
+            }).collect()
+        }).ok_or(EIO)
+    }
+
+    /// Todas as posições do bloco de ponteiros `block_index`, ou `None` se ele não puder ser lido.
+    fn read_slots(&self, block_index: usize) -> Option<Vec<Option<usize>>> {
+        self.with_memory_block(block_index, |data| {
+            data.chunks(POINTER_SIZE).map(|bytes| {
+                let mut pointer = [0u8; POINTER_SIZE];
+                pointer.copy_from_slice(bytes);
+                (u64::from_le_bytes(pointer) as usize).checked_sub(1)
+            }).collect()
+        })
+    }
+
+    /// Lê o ponteiro da posição `slot` do bloco de ponteiros `block_index`.
+    fn read_pointer(&self, block_index: usize, slot: usize) -> Result<Option<usize>, c_int> {
+        self.read_pointers(block_index, slot..slot + 1).map(|pointers| pointers.first().copied())
+    }
+
+    /// Faz as posições `slots` do bloco de ponteiros `block_index` apontarem para o bloco `pointer`.
+    fn write_pointers(&mut self, block_index: usize, slots: Range<usize>, pointer: Option<usize>) -> Result<(), c_int> {
+        let mut data = self.take_memory_block(block_index)?;
+        let bytes = pointer.map_or(0, |pointer| pointer as u64 + 1).to_le_bytes();
+
+        for slot in slots {
+            data[slot * POINTER_SIZE..(slot + 1) * POINTER_SIZE].copy_from_slice(&bytes);
+        }
+
+        self.write_content_as_bytes(block_index, data.into_boxed_slice());
+        Ok(())
+    }
+
+    /// Retorna o bloco de memória do bloco lógico `logical` do arquivo com as references `references`, se houver.
+    fn file_block(&self, references: &[Option<usize>; 128], logical: usize) -> Result<Option<usize>, c_int> {
+        match self.block_path(logical).ok_or(EFBIG)? {
+            BlockPath::Direct(index) => Ok(references[index]),
+            BlockPath::Single(slot) => match references[SINGLE_INDIRECT] {
+                Some(pointers) => self.read_pointer(pointers, slot),
+                None => Ok(None)
             },
+            BlockPath::Double(outer, inner) => match references[DOUBLE_INDIRECT] {
+                Some(outer_pointers) => match self.read_pointer(outer_pointers, outer)? {
+                    Some(pointers) => self.read_pointer(pointers, inner),
+                    None => Ok(None)
+                },
+                None => Ok(None)
+            }
+        }
+    }
+
+    /// Todos os blocos de memória usados pelo arquivo `inode`, inclusive os blocos de ponteiros.
+    fn file_blocks(&self, inode: &Inode) -> Result<Vec<usize>, c_int> {
+        let pointers = self.pointers_per_block();
+        let mut blocks: Vec<usize> = inode.references.iter().flatten().copied().collect();
+
+        if let Some(single) = inode.references[SINGLE_INDIRECT] {
+            blocks.extend(self.read_pointers(single, 0..pointers)?);
+        }
+
+        if let Some(double) = inode.references[DOUBLE_INDIRECT] {
+            for outer_pointers in self.read_pointers(double, 0..pointers)? {
+                blocks.push(outer_pointers);
+                blocks.extend(self.read_pointers(outer_pointers, 0..pointers)?);
+            }
+        }
+
+        Ok(blocks)
+    }
+
+    /// Blocos de memória usados pelo arquivo `ino`, inclusive os blocos de ponteiros.
+    pub fn file_map(&self, ino: u64) -> Result<FileMap, c_int> {
+        let inode = self.get_inode(ino).ok_or_else(|| self.inode_error(ino))?;
+        let pointers = self.pointers_per_block();
+        let mut data_blocks: Vec<(usize, usize)> = (0..DIRECT_REFERENCES)
+            .filter_map(|logical| inode.references[logical].map(|block_index| (logical, block_index)))
+            .collect();
+        let mut pointer_blocks: Vec<usize> = Vec::new();
+
+        if let Some(single) = inode.references[SINGLE_INDIRECT] {
+            pointer_blocks.push(single);
+            for (slot, block_index) in self.read_slots(single).ok_or(EIO)?.into_iter().enumerate() {
+                if let Some(block_index) = block_index {
+                    data_blocks.push((DIRECT_REFERENCES + slot, block_index));
+                }
+            }
+        }
+
+        if let Some(double) = inode.references[DOUBLE_INDIRECT] {
+            let first = DIRECT_REFERENCES + pointers;
+            pointer_blocks.push(double);
+
+            for (outer, outer_pointers) in self.read_slots(double).ok_or(EIO)?.into_iter().enumerate() {
+                if let Some(outer_pointers) = outer_pointers {
+                    pointer_blocks.push(outer_pointers);
+                    for (inner, block_index) in self.read_slots(outer_pointers).ok_or(EIO)?.into_iter().enumerate() {
+                        if let Some(block_index) = block_index {
+                            data_blocks.push((first + outer * pointers + inner, block_index));
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(FileMap { data_blocks, pointer_blocks })
+    }
+
+    /// Aloca um bloco de memória zerado.
+    fn allocate_block(&mut self) -> Result<usize, c_int> {
+        let block_index = self.find_index_of_empty_memory_block().ok_or(ENOSPC)?;
+        self.write_content_as_bytes(block_index, Box::default());
+        Ok(block_index)
+    }
+
+    /// Retorna o bloco apontado por `reference`, alocando um caso ainda não exista.
+    fn allocate_reference(&mut self, reference: &mut Option<usize>) -> Result<usize, c_int> {
+        match *reference {
+            Some(block_index) => Ok(block_index),
+            None => {
+                let block_index = self.allocate_block()?;
+                *reference = Some(block_index);
+                Ok(block_index)
+            }
+        }
+    }
+
+    /// Retorna o bloco apontado pela posição `slot` do bloco de ponteiros `pointers`, alocando um caso ainda não exista.
+    fn allocate_pointer(&mut self, pointers: usize, slot: usize) -> Result<usize, c_int> {
+        match self.read_pointer(pointers, slot)? {
+            Some(block_index) => Ok(block_index),
+            None => {
+                let block_index = self.allocate_block()?;
+                self.write_pointers(pointers, slot..slot + 1, Some(block_index))?;
+                Ok(block_index)
+            }
+        }
+    }
+
+    /// Retorna o bloco de memória do bloco lógico `logical` do arquivo com as references `references`, alocando ele
+    /// e os blocos de ponteiros que faltarem.
+    fn allocate_file_block(&mut self, references: &mut [Option<usize>; 128], logical: usize) -> Result<usize, c_int> {
+        let (pointers, slot) = match self.block_path(logical).ok_or(EFBIG)? {
+            BlockPath::Direct(index) => return self.allocate_reference(&mut references[index]),
+            BlockPath::Single(slot) => (self.allocate_reference(&mut references[SINGLE_INDIRECT])?, slot),
+            BlockPath::Double(outer, inner) => {
+                let outer_pointers = self.allocate_reference(&mut references[DOUBLE_INDIRECT])?;
+                (self.allocate_pointer(outer_pointers, outer)?, inner)
+            }
         };
 
-        match serialize(&self.memory_blocks) {
-            Err(e) => {
-                print!("Erro ao tentar escrever para arquivo de disco! {}", e);
-                return;
-            },
-            Ok(v) => {
-                let disk_file = format!("{}/.disco.risos", &self.root_path);
-                let mut disk_file = OpenOptions::new().write(true).open(disk_file).unwrap();
-                match disk_file.write(&v) {
-                    Err(e) => {
-                        print!("Erro ao tentar escrever para arquivo de inodes! {}", e);
-                        return;
-                    },
-                    Ok(v) => v,
+        self.allocate_pointer(pointers, slot)
+    }
+
+    /// Libera os blocos lógicos a partir de `block_quantity` do arquivo com as references `references`, junto com os
+    /// blocos de ponteiros que deixam de ser usados. Os ponteiros mantidos que apontavam para blocos liberados são
+    /// zerados, para que não sejam confundidos com blocos alocados se o arquivo voltar a crescer. Todos os blocos
+    /// de ponteiros são lidos antes de qualquer alteração, então um erro de leitura não deixa nada pela metade.
+    fn free_file_blocks(&mut self, references: &mut [Option<usize>; 128], block_quantity: usize) -> Result<(), c_int> {
+        let pointers = self.pointers_per_block();
+        let mut freed: Vec<usize> = Vec::new();
+        // Posições de blocos de ponteiros mantidos que precisam ser zeradas
+        let mut cleared: Vec<(usize, Range<usize>)> = Vec::new();
+
+        for reference in references.iter_mut().take(DIRECT_REFERENCES).skip(block_quantity) {
+            freed.extend(reference.take());
+        }
+
+        if let Some(single) = references[SINGLE_INDIRECT] {
+            let first = block_quantity.saturating_sub(DIRECT_REFERENCES);
+            freed.extend(self.read_pointers(single, first.min(pointers)..pointers)?);
+
+            if first == 0 {
+                freed.push(single);
+                references[SINGLE_INDIRECT] = None;
+            } else if first < pointers {
+                cleared.push((single, first..pointers));
+            }
+        }
+
+        if let Some(double) = references[DOUBLE_INDIRECT] {
+            let first = block_quantity.saturating_sub(DIRECT_REFERENCES + pointers);
+            // Posições do bloco duplamente indireto que continuam em uso
+            let kept = first.div_ceil(pointers);
+
+            for outer in 0..pointers {
+                let outer_pointers = match self.read_pointer(double, outer)? {
+                    Some(outer_pointers) => outer_pointers,
+                    None => continue
                 };
-            },
+
+                if outer >= kept {
+                    freed.extend(self.read_pointers(outer_pointers, 0..pointers)?);
+                    freed.push(outer_pointers);
+                } else if outer == kept - 1 && !first.is_multiple_of(pointers) {
+                    freed.extend(self.read_pointers(outer_pointers, first % pointers..pointers)?);
+                    cleared.push((outer_pointers, first % pointers..pointers));
+                }
+            }
+
+            if kept == 0 {
+                freed.push(double);
+                references[DOUBLE_INDIRECT] = None;
+            } else {
+                cleared.push((double, kept..pointers));
+            }
+        }
+
+        for (block_index, slots) in cleared {
+            self.write_pointers(block_index, slots, None)?;
+        }
+
+        for block_index in freed {
+            self.clear_memory_block(block_index);
+        }
+
+        Ok(())
+    }
+
+    /// Retira as alterações feitas desde a última transação, com o valor atual de cada posição alterada.
+    fn take_transaction(&mut self) -> Transaction {
+        let mut dirty_inodes: Vec<usize> = self.dirty_inodes.drain().collect();
+        dirty_inodes.sort_unstable();
+
+        let mut blocks: Vec<(usize, Vec<u8>)> = self.dirty_blocks.drain()
+            .map(|(block_index, data)| (block_index, data.into_vec()))
+            .collect();
+        blocks.sort_unstable_by_key(|(block_index, _)| *block_index);
+
+        Transaction {
+            sequence: 0,
+            inodes: dirty_inodes.into_iter().map(|index| (index, self.super_block[index].clone())).collect(),
+            inode_bitmap: self.inode_bitmap.take_dirty_words(),
+            block_bitmap: self.block_bitmap.take_dirty_words(),
+            blocks
+        }
+    }
+
+    /// Tamanho máximo que a transação com as alterações feitas desde a anterior ocupa no journal, em bytes.
+    fn transaction_size(&self) -> u64 {
+        let words = self.inode_bitmap.dirty_word_count() + self.block_bitmap.dirty_word_count();
+
+        TRANSACTION_FIELDS_SIZE
+            + self.dirty_inodes.len() as u64 * INODE_RECORD_SIZE
+            + words as u64 * WORD_RECORD_SIZE
+            + self.dirty_blocks.len() as u64 * (BLOCK_RECORD_SIZE + self.block_size as u64)
+    }
+
+    /// Quantidade de blocos de memória que a operação atual ainda pode alterar sem que a transação deixe de caber no
+    /// journal. Para cada bloco é contada a palavra do mapa de blocos que ele pode alterar e, a cada
+    /// `pointers_per_block` blocos, um bloco de ponteiros. Sem limite se o disco estiver somente para leitura.
+    fn transaction_room(&self) -> usize {
+        if self.read_only {
+            return usize::MAX;
+        }
+
+        let bytes = self.journal.max_transaction_size().saturating_sub(self.transaction_size() + TRANSACTION_RESERVE);
+        let blocks = (bytes / (BLOCK_RECORD_SIZE + WORD_RECORD_SIZE + self.block_size as u64)) as usize;
+
+        // Os blocos de ponteiros de indireção simples e dupla, além de um para cada `pointers_per_block` blocos
+        let blocks = blocks.saturating_sub(2);
+        blocks - blocks.div_ceil(self.pointers_per_block() + 1)
+    }
+
+    /// Grava as alterações no journal se elas já ocuparem metade do espaço de uma transação. Usado entre as alterações
+    /// independentes de uma tarefa que altera o disco inteiro, como a liberação dos arquivos removidos ao montar o
+    /// disco e as correções do fsck, para que elas nunca formem uma transação maior que o journal.
+    fn commit_if_large(&mut self) {
+        if self.read_only || self.transaction_size() * 2 <= self.journal.max_transaction_size() {
+            return;
+        }
+
+        // Com um erro as alterações continuam em memória, e o commit seguinte tenta gravá-las de novo
+        let _ = self.commit();
+    }
+
+    /// Grava no journal, como uma única transação, tudo o que foi alterado desde a transação anterior. Deve ser
+    /// chamado ao fim de cada operação do FS que altera o disco, e nunca no meio dela, para que a operação seja refeita
+    /// inteira ou não seja refeita depois de uma queda. Se o journal estiver cheio, as transações anteriores são
+    /// aplicadas na imagem antes.
+    ///
+    /// Retorna `EIO` se as alterações não puderem ser gravadas ou não couberem no journal inteiro. Elas continuam em
+    /// memória e são gravadas de novo no próximo commit.
+    pub fn commit(&mut self) -> Result<(), c_int> {
+        if let Err(e) = self.commit_transaction() {
+            println!("Erro ao gravar as alterações no journal! {}", e);
+            return Err(EIO);
+        }
+
+        // As alterações já estão no journal, então um erro aqui só adia a aplicação delas na imagem
+        if self.pending.blocks.len() > self.cache_capacity {
+            if let Err(e) = self.checkpoint() {
+                println!("Erro ao aplicar o journal na imagem! {}", e);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Grava no journal a transação com as alterações ainda não gravadas. Se não for possível, a transação continua em
+    /// memória e é aplicada junto com as demais no próximo checkpoint.
+    fn commit_transaction(&mut self) -> io::Result<()> {
+        if self.read_only {
+            return Ok(());
+        }
+
+        // Transações posteriores a uma que não chegou ao journal não podem ser refeitas sozinhas depois de uma queda,
+        // então a que falhou é aplicada na imagem antes de gravar outras no journal
+        if self.unjournaled {
+            self.checkpoint()?;
+        }
+
+        // Alterações que não cabem no journal nunca são gravadas direto na imagem: elas ficam em memória e o commit
+        // falha. As operações conferem o espaço da transação com `transaction_room` para que isso não aconteça
+        if self.transaction_size() > self.journal.max_transaction_size() {
+            return Err(io::Error::other("as alterações pendentes não cabem no journal"));
+        }
+
+        let mut transaction = self.take_transaction();
+        if transaction.is_empty() {
+            return Ok(());
+        }
+
+        let appended = match self.journal.append(&self.image, &mut transaction) {
+            Ok(false) => self.checkpoint().and_then(|_| self.journal.append(&self.image, &mut transaction)),
+            result => result
         };
+
+        match appended {
+            Ok(true) => {
+                self.pending.merge(transaction);
+                Ok(())
+            },
+            // O tamanho já foi conferido, então a transação sempre cabe no journal vazio
+            Ok(false) => unreachable!("transação {} maior que o journal", transaction.sequence),
+            Err(e) => {
+                self.pending.merge(transaction);
+                self.unjournaled = true;
+                Err(e)
+            }
+        }
+    }
+
+    /// Garante que todas as alterações feitas até agora chegaram ao dispositivo de armazenamento: grava as alterações
+    /// pendentes no journal e sincroniza a imagem. Depois de uma queda, elas são refeitas ao montar o disco.
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.commit_transaction()?;
+        self.image.sync()
+    }
+
+    /// Aplica na imagem as transações gravadas no journal e esvazia o journal. A imagem só é alterada depois que o
+    /// journal chegou ao dispositivo, e o journal só é esvaziado depois que as alterações chegaram à imagem.
+    pub fn checkpoint(&mut self) -> io::Result<()> {
+        if self.read_only || (self.pending.is_empty() && self.journal.is_empty()) {
+            return Ok(());
+        }
+
+        self.image.sync()?;
+
+        let transaction = mem::take(&mut self.pending).into_transaction();
+        if let Err(e) = transaction.apply(&self.image).and_then(|_| self.image.sync()) {
+            self.pending.merge(transaction);
+            return Err(e);
+        }
+
+        self.unjournaled = false;
+        self.journal.reset(&self.image)?;
+
+        for (block_index, data) in transaction.blocks {
+            self.cache_block(block_index, data.into_boxed_slice());
+        }
+
+        Ok(())
+    }
+
+    /// Grava no journal as alterações pendentes e aplica todas as transações na imagem, deixando o journal vazio.
+    pub fn write_to_disk(&mut self) -> io::Result<()> {
+        self.commit_transaction()?;
+        self.checkpoint()
+    }
+
+    /// Indica se tudo o que foi alterado já está aplicado na imagem, ou seja, se `write_to_disk` não tem o que gravar.
+    pub fn is_clean(&self) -> bool {
+        self.dirty_inodes.is_empty() && self.dirty_blocks.is_empty()
+            && !self.inode_bitmap.is_dirty() && !self.block_bitmap.is_dirty()
+            && self.pending.is_empty() && self.journal.is_empty()
+    }
+}
+
+/// Bytes de um mapa de bits lido da imagem com as palavras `words` substituídas.
+fn with_words(mut bytes: Vec<u8>, words: &HashMap<usize, u64>) -> Vec<u8> {
+    for (index, word) in words.iter() {
+        bytes[index * 8..(index + 1) * 8].copy_from_slice(&word.to_le_bytes());
+    }
+
+    bytes
+}
+
+/// Inode de um diretório novo e vazio, com o "." e a entrada no pai `parent`.
+fn directory_inode(ino: u64, parent: u64, perm: u16, uid: u32, gid: u32) -> Inode {
+    let ts = time::now().to_timespec();
+    let attr = FileAttr {
+        ino,
+        size: 0,
+        blocks: 0,
+        atime: ts,
+        mtime: ts,
+        ctime: ts,
+        crtime: ts,
+        kind: FileType::Directory,
+        perm,
+        nlink: 2,
+        uid,
+        gid,
+        rdev: 0,
+        flags: 0,
+    };
+
+    Inode {
+        attributes: attr,
+        references: [None; 128],
+        parent
+    }
+}
+
+/// Hash de 64 bits do nome de uma entrada de diretório (FNV-1a seguido do finalizador do MurmurHash3). O valor é
+/// persistido indiretamente na posição das entradas, então não pode depender da versão do Rust.
+pub fn name_hash(name: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in name {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    hash ^ (hash >> 33)
+}
+
+/// Posição da tabela de um índice com profundidade `depth` onde fica o hash `hash`, dada pelos seus `depth` bits mais
+/// significativos. Como os bits mais significativos são usados, dobrar a tabela mantém a ordem global dos hashes.
+fn slot_of(hash: u64, depth: u32) -> usize {
+    match depth {
+        0 => 0,
+        depth => (hash >> (64 - depth)) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::process;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static NEXT_IMAGE: AtomicUsize = AtomicUsize::new(0);
+
+    /// Imagem em um arquivo temporário, apagado ao fim do teste.
+    pub(super) struct TempImage(pub(super) PathBuf);
+
+    impl TempImage {
+        pub(super) fn new() -> TempImage {
+            let name = format!("risos-test-{}-{}.img", process::id(), NEXT_IMAGE.fetch_add(1, Ordering::SeqCst));
+            TempImage(env::temp_dir().join(name))
+        }
+
+        /// Formata a imagem com blocos de 1 KiB e a carrega.
+        pub(super) fn disk(&self, disk_size: u64) -> Disk {
+            self.disk_with_blocks(disk_size, 1024)
+        }
+
+        /// Formata a imagem com blocos de `block_size` bytes e a carrega.
+        pub(super) fn disk_with_blocks(&self, disk_size: u64, block_size: usize) -> Disk {
+            Disk::format(&self.0, SuperBlock::format(disk_size, block_size, 64).unwrap()).unwrap();
+            Disk::new(&self.0).unwrap()
+        }
+    }
+
+    impl Drop for TempImage {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    /// Cria no diretório `parent` um Inode do tipo `kind` chamado `name`.
+    pub(super) fn create(disk: &mut Disk, parent: u64, name: &str, kind: FileType) -> u64 {
+        let ino = disk.find_ino_available().unwrap();
+        let mut inode = directory_inode(ino, parent, 0o644, 0, 0);
+        inode.attributes.kind = kind;
+        if kind != FileType::Directory {
+            inode.attributes.nlink = 1;
+        }
+
+        disk.write_inode(inode);
+        disk.add_directory_entry(parent, OsStr::new(name), ino, kind).unwrap();
+        if kind == FileType::Directory {
+            disk.increment_nlink(parent);
+        }
+
+        ino
+    }
+
+    #[test]
+    fn doubling_table_keeps_hash_order() {
+        let hashes: Vec<u64> = (0..1000).map(|i| name_hash(format!("arquivo-{}", i).as_bytes())).collect();
+
+        for depth in [0, 1, 2, 6, 14, 47].iter().copied() {
+            for hash in hashes.iter().copied() {
+                // Cada posição se divide em duas consecutivas
+                assert_eq!(slot_of(hash, depth + 1) / 2, slot_of(hash, depth));
+            }
+
+            let mut sorted = hashes.clone();
+            sorted.sort();
+            assert!(sorted.windows(2).all(|pair| slot_of(pair[0], depth) <= slot_of(pair[1], depth)));
+        }
+    }
+
+    #[test]
+    fn name_hash_is_stable() {
+        // O hash é persistido indiretamente na posição das entradas, então não pode mudar
+        assert_eq!(name_hash(b""), 0xefd0_1f60_ba99_2926);
+        assert_eq!(name_hash(b"risos"), 0xf8da_7738_0862_34d5);
+    }
+
+    #[test]
+    fn directory_stays_in_hash_order_while_growing() {
+        let image = TempImage::new();
+        let mut disk = image.disk(8 << 20);
+        let file = create(&mut disk, 1, "arquivo", FileType::RegularFile);
+
+        for i in 0..300 {
+            disk.add_directory_entry(1, OsStr::new(&format!("link-{}", i)), file, FileType::RegularFile).unwrap();
+        }
+
+        assert!(disk.directory_depth(1).unwrap().unwrap() > 0);
+
+        let entries = disk.read_directory(1).unwrap();
+        assert_eq!(entries.len(), 301);
+        let hashes: Vec<u64> = entries.iter().map(|entry| name_hash(&entry.name)).collect();
+        assert!(hashes.windows(2).all(|pair| pair[0] <= pair[1]));
+
+        // Uma listagem retomada a partir de um hash continua exatamente de onde a anterior parou, um bucket por vez
+        let mut resumed: Vec<DirectoryEntry> = Vec::new();
+        let mut next = Some(hashes[150]);
+        while let Some(min_hash) = next {
+            let (bucket_entries, following) = disk.read_directory_bucket(1, min_hash).unwrap();
+            assert!(following.is_none_or(|following| following > min_hash));
+            resumed.extend(bucket_entries);
+            next = following;
+        }
+        assert_eq!(resumed.len(), 301 - 150);
+        assert_eq!(resumed[0].name, entries[150].name);
+
+        for i in 0..300 {
+            let name = format!("link-{}", i);
+            assert_eq!(disk.find_directory_entry(1, OsStr::new(&name)).unwrap().ino, file);
+        }
+    }
+
+    #[test]
+    fn directory_grows_past_the_old_bucket_limit() {
+        let image = TempImage::new();
+        let mut disk = image.disk_with_blocks(256 << 20, 2048);
+        let file = create(&mut disk, 1, "arquivo", FileType::RegularFile);
+
+        // Com nomes de 255 bytes, cabem 7 entradas em um bucket de 2 KiB: mais entradas do que 16384 buckets
+        // cheios comportam
+        let name = |i: usize| format!("{:0>255}", i);
+        let count = (1 << 14) * 7 + 1;
+        for i in 0..count {
+            disk.add_directory_entry(1, OsStr::new(&name(i)), file, FileType::RegularFile).unwrap();
+            disk.increment_nlink(file);
+            if i % 1000 == 0 {
+                disk.commit().unwrap();
+            }
+        }
+        disk.commit().unwrap();
+        drop(disk);
+
+        let mut disk = Disk::load(&image.0, true).unwrap();
+        assert!(disk.get_inode(1).unwrap().attributes.size / 2048 > 1 << 14);
+        assert_eq!(disk.read_directory(1).unwrap().len(), count + 1);
+        for i in (0..count).step_by(997) {
+            assert_eq!(disk.find_directory_entry(1, OsStr::new(&name(i))).unwrap().ino, file);
+        }
+        assert_eq!(disk.check().unwrap().problems, 0);
+    }
+
+    /// Conteúdo de teste com `len` bytes que não se repete a cada bloco.
+    fn pattern(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 31 % 251) as u8).collect()
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn write_crosses_indirect_boundaries() {
+        let image = TempImage::new();
+        let mut disk = image.disk(8 << 20);
+        let file = create(&mut disk, 1, "arquivo", FileType::RegularFile);
+        let free_before = disk.free_memory_blocks();
+
+        // Dos blocos diretos até os de indireção dupla: 126 diretos e 128 de indireção simples com blocos de 1 KiB
+        let offset = 120 * 1024 + 10;
+        let data = pattern(140 * 1024);
+        assert_eq!(disk.write_content(file, offset, &data).unwrap(), data.len());
+        assert_eq!(disk.read_content(file, offset, data.len()).unwrap(), data);
+
+        // O começo do arquivo ficou como um buraco
+        assert_eq!(disk.read_content(file, 0, 1024).unwrap(), vec![0; 1024]);
+
+        let file_map = disk.file_map(file).unwrap();
+        let logicals: Vec<usize> = file_map.data_blocks.iter().map(|(logical, _)| *logical).collect();
+        assert_eq!(logicals, (120..=260).collect::<Vec<usize>>());
+        // Indireção simples, indireção dupla e um bloco de ponteiros dentro dela
+        assert_eq!(file_map.pointer_blocks.len(), 3);
+
+        let used = free_before - disk.free_memory_blocks();
+        assert_eq!(used, logicals.len() + 3);
+        assert_eq!(disk.get_inode(file).unwrap().attributes.blocks, disk.blocks_to_sectors(used));
+        assert_eq!(disk.get_inode(file).unwrap().attributes.size, offset + data.len() as u64);
+    }
+
+    #[test]
+    fn resize_frees_pointer_blocks_across_boundaries() {
+        let image = TempImage::new();
+        let mut disk = image.disk(8 << 20);
+        let file = create(&mut disk, 1, "arquivo", FileType::RegularFile);
+        let free_before = disk.free_memory_blocks();
+
+        let data = pattern(300 * 1024);
+        disk.write_content(file, 0, &data).unwrap();
+        assert_eq!(free_before - disk.free_memory_blocks(), 300 + 3);
+
+        // Somente os blocos de indireção simples
+        disk.resize_content(file, 200 * 1024).unwrap();
+        assert_eq!(free_before - disk.free_memory_blocks(), 200 + 1);
+        assert_eq!(disk.file_map(file).unwrap().pointer_blocks.len(), 1);
+
+        // Somente os blocos diretos
+        disk.resize_content(file, 100 * 1024 + 1).unwrap();
+        assert_eq!(free_before - disk.free_memory_blocks(), 101);
+        assert!(disk.file_map(file).unwrap().pointer_blocks.is_empty());
+        assert_eq!(disk.read_content(file, 0, data.len()).unwrap(), &data[..100 * 1024 + 1]);
+
+        disk.resize_content(file, 0).unwrap();
+        assert_eq!(disk.free_memory_blocks(), free_before);
+        assert_eq!(disk.get_inode(file).unwrap().attributes.blocks, 0);
+    }
+
+    #[test]
+    fn resize_grows_without_allocating() {
+        let image = TempImage::new();
+        let mut disk = image.disk(8 << 20);
+        let file = create(&mut disk, 1, "arquivo", FileType::RegularFile);
+        disk.write_content(file, 0, b"inicio").unwrap();
+        let free_before = disk.free_memory_blocks();
+
+        disk.resize_content(file, 300 * 1024).unwrap();
+        assert_eq!(disk.free_memory_blocks(), free_before);
+        assert_eq!(disk.get_inode(file).unwrap().attributes.size, 300 * 1024);
+
+        let content = disk.read_content(file, 0, 300 * 1024).unwrap();
+        assert_eq!(&content[..6], b"inicio");
+        assert!(content[6..].iter().all(|byte| *byte == 0));
+
+        // Escrever depois do buraco aloca somente o bloco escrito e os blocos de ponteiros dele
+        disk.write_content(file, 299 * 1024, b"fim").unwrap();
+        assert_eq!(free_before - disk.free_memory_blocks(), 1 + 2);
+    }
+
+    #[test]
+    fn journal_is_replayed_after_drop_without_checkpoint() {
+        let image = TempImage::new();
+        let mut disk = image.disk(8 << 20);
+        let file = create(&mut disk, 1, "arquivo", FileType::RegularFile);
+        disk.write_content(file, 0, b"somente no journal").unwrap();
+        disk.commit().unwrap();
+        drop(disk);
+
+        // As alterações ainda não chegaram à tabela de Inodes
+        let inodes = Image::open(&image.0, true).unwrap().read_inodes().unwrap();
+        assert!(inodes[file as usize - 1].as_ref().unwrap().is_none());
+
+        let disk = Disk::new(&image.0).unwrap();
+        assert!(disk.journal.is_empty());
+        assert_eq!(disk.find_directory_entry(1, OsStr::new("arquivo")).unwrap().ino, file);
+        assert_eq!(disk.read_content(file, 0, 100).unwrap(), b"somente no journal");
+    }
+
+    #[test]
+    fn large_write_is_split_to_fit_the_journal() {
+        let image = TempImage::new();
+        let mut disk = image.disk(8 << 20);
+        let file = create(&mut disk, 1, "arquivo", FileType::RegularFile);
+        disk.commit().unwrap();
+
+        // O journal de um disco de 8 MiB tem 1 MiB, menos do que a escrita inteira
+        let data = pattern(3 << 20);
+        let written = disk.write_content(file, 0, &data).unwrap();
+        assert!(written > 0 && written < data.len());
+        disk.commit().unwrap();
+        drop(disk);
+
+        // A parte escrita é uma transação completa do journal, refeita depois de uma queda
+        let mut disk = Disk::new(&image.0).unwrap();
+        assert_eq!(disk.get_inode(file).unwrap().attributes.size, written as u64);
+        assert_eq!(disk.read_content(file, 0, written).unwrap(), &data[..written]);
+
+        let mut offset = written;
+        while offset < data.len() {
+            offset += disk.write_content(file, offset as u64, &data[offset..]).unwrap();
+            disk.commit().unwrap();
+        }
+        assert_eq!(disk.read_content(file, 0, data.len()).unwrap(), data);
+    }
+
+    #[test]
+    fn commit_refuses_changes_larger_than_the_journal() {
+        let image = TempImage::new();
+        let mut disk = image.disk(8 << 20);
+        let blocks: Vec<usize> = (0..1200).map(|_| {
+            let block_index = disk.find_index_of_empty_memory_block().unwrap();
+            disk.write_content_as_bytes(block_index, Box::from(&b"grande demais"[..]));
+            block_index
+        }).collect();
+
+        assert_eq!(disk.commit(), Err(EIO));
+        assert!(!disk.is_clean());
+        drop(disk);
+
+        // Nada foi gravado direto na imagem
+        let block_bitmap = Image::open(&image.0, true).unwrap().read_bitmap(false).unwrap();
+        assert!(blocks.iter().all(|block_index| block_bitmap[block_index / 8] & (1 << (block_index % 8)) == 0));
+    }
+
+    #[test]
+    fn inode_record_fits_its_reserved_size() {
+        let mut inode = directory_inode(u64::MAX, u64::MAX, 0o777, u32::MAX, u32::MAX);
+        inode.references = [Some(usize::MAX); 128];
+        assert!(serialized_size(&(usize::MAX, Some(inode))).unwrap() <= INODE_RECORD_SIZE);
+    }
+
+    #[test]
+    fn torn_last_transaction_is_discarded() {
+        let image = TempImage::new();
+        let mut disk = image.disk(8 << 20);
+        let first = create(&mut disk, 1, "primeiro", FileType::RegularFile);
+        disk.write_content(first, 0, b"completo").unwrap();
+        disk.commit().unwrap();
+        let second = create(&mut disk, 1, "segundo", FileType::RegularFile);
+        disk.write_content(second, 0, b"pela metade").unwrap();
+        disk.commit().unwrap();
+        drop(disk);
+
+        // A última transação é a última coisa gravada no journal; alterar o seu último byte simula uma gravação
+        // interrompida
+        let image_file = Image::open(&image.0, false).unwrap();
+        let mut journal = vec![0u8; image_file.super_block.journal_size as usize];
+        image_file.read_journal(0, &mut journal).unwrap();
+        let last = journal.iter().rposition(|byte| *byte != 0).unwrap();
+        image_file.write_journal(last as u64, &[!journal[last]]).unwrap();
+        drop(image_file);
+
+        let disk = Disk::new(&image.0).unwrap();
+        assert_eq!(disk.read_content(first, 0, 100).unwrap(), b"completo");
+        assert!(disk.find_directory_entry(1, OsStr::new("segundo")).is_none());
+        assert!(disk.get_inode(second).is_none());
+        assert!(!disk.bitmap(true).is_set(second as usize - 1));
+    }
+}
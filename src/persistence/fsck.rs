@@ -0,0 +1,899 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::ffi::OsStr;
+use std::io;
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use fuse::FileType;
+use libc::{c_int, ENOSPC};
+use super::{Disk, DirectoryEntry, NAME_MAX, DIRECT_REFERENCES, SINGLE_INDIRECT, DOUBLE_INDIRECT};
+use super::{slot_of, name_hash, directory_inode};
+
+/// Diretório do root onde são colocados os Inodes que não são alcançáveis a partir do root
+pub const LOST_AND_FOUND: &str = "lost+found";
+
+/// Resultado de `Disk::check`
+pub struct CheckSummary {
+    /// Quantidade de problemas encontrados
+    pub problems: usize,
+    /// Quantidade de problemas que não puderam ser corrigidos
+    pub unrepaired: usize
+}
+
+/// Posição de um ponteiro para um bloco de memória
+enum Pointer {
+    /// Posição de `references` do Inode
+    Reference(usize),
+    /// Posição de um bloco de ponteiros
+    Slot(usize, usize)
+}
+
+/// Ponteiro inválido encontrado ao conferir os blocos de um Inode
+struct InvalidPointer {
+    pointer: Pointer,
+    /// Bloco para o qual o ponteiro aponta
+    block_index: usize,
+    reason: String,
+    /// Bloco lógico do arquivo, se o ponteiro for para um bloco com conteúdo que pertence a outro Inode ou que se
+    /// repete no próprio Inode; o conteúdo é copiado para um bloco novo
+    shared: Option<usize>
+}
+
+/// Estado de uma verificação do disco
+#[derive(Default)]
+struct Check {
+    /// Inodes já alcançados, a partir do root ou como órfãos
+    visited: HashSet<u64>,
+    /// Inode dono de cada bloco de memória em uso
+    owners: HashMap<usize, u64>,
+    /// Bloco de onde vem o conteúdo de cada bloco lógico `(ino, bloco lógico)` que era compartilhado
+    copies: HashMap<(u64, usize), usize>,
+    /// Diretórios que precisam ser reescritos, com as entradas válidas
+    rebuild: Vec<(u64, Vec<DirectoryEntry>)>,
+    /// Inodes órfãos que vão para o lost+found
+    lost: Vec<u64>,
+    problems: usize,
+    unrepaired: usize
+}
+
+impl Check {
+    fn problem(&mut self, message: String) {
+        println!("{}", message);
+        self.problems += 1;
+    }
+
+    fn unrepaired(&mut self, message: String) {
+        println!("{}", message);
+        self.unrepaired += 1;
+    }
+}
+
+impl Disk {
+    /// Verifica a consistência do disco, registrando no log cada problema encontrado, e corrige todos eles em memória:
+    ///
+    /// - registros de Inodes cujo checksum não confere, que são recuperados se ainda puderem ser lidos ou liberados;
+    /// - Inodes e blocos de memória marcados errado nos mapas de bits;
+    /// - ponteiros para blocos fora do disco, ilegíveis ou que pertencem a outro arquivo;
+    /// - blocos após o fim do arquivo;
+    /// - entradas de diretório inválidas, repetidas, fora do bucket, com o tipo errado ou que apontam para Inodes
+    ///   inexistentes, e entradas que formariam um ciclo ou dariam um segundo pai a um diretório;
+    /// - índices de diretório corrompidos e buckets ilegíveis, cujas entradas são perdidas;
+    /// - Inodes que não são alcançáveis a partir do root, que vão para o `lost+found` (os arquivos vazios ou sem
+    ///   nenhuma ligação são simplesmente liberados);
+    /// - `nlink`, `blocks` e o ".." de diretórios errados.
+    ///
+    /// As correções são gravadas no journal aos poucos, sempre que ocupam metade de uma transação, e as últimas só
+    /// chegam à imagem com `write_to_disk`; para somente verificar, o disco deve ser carregado somente para leitura
+    /// (`Disk::load` com `read_only`). Retorna um erro se o root não for um diretório.
+    pub fn check(&mut self) -> io::Result<CheckSummary> {
+        let mut check = Check::default();
+
+        self.check_inode_table(&mut check)?;
+        check.visited.insert(1);
+        self.check_tree(&mut check, 1);
+        self.check_orphans(&mut check);
+        self.check_block_bitmap(&mut check);
+
+        // Só com o mapa de blocos correto é seguro alocar blocos novos
+        self.copy_shared_blocks(&mut check);
+        self.rebuild_directories(&mut check);
+        self.adopt_orphans(&mut check);
+        self.check_counts(&mut check);
+
+        Ok(CheckSummary { problems: check.problems, unrepaired: check.unrepaired })
+    }
+
+    /// Confere os registros corrompidos, o número de cada Inode, o mapa de Inodes e o root, criando um root vazio caso
+    /// ele não exista.
+    fn check_inode_table(&mut self, check: &mut Check) -> io::Result<()> {
+        let mut corrupted: Vec<usize> = self.corrupted_inodes.iter().copied().collect();
+        corrupted.sort_unstable();
+
+        // O conteúdo de um registro corrompido que ainda pode ser lido é mantido e conferido como o dos demais Inodes,
+        // indo para o lost+found se não for mais alcançável. Os outros são liberados, e as entradas que apontavam para
+        // eles são removidas. Nos dois casos o registro é regravado com um checksum válido
+        for index in corrupted {
+            let ino = index as u64 + 1;
+
+            match self.image.read_inode_unchecked(index) {
+                Ok(Some(mut inode)) => {
+                    check.problem(format!("Inode {}: o checksum do registro não confere; o conteúdo dele é mantido", ino));
+                    inode.attributes.ino = ino;
+                    self.write_inode(inode);
+                },
+                _ => {
+                    check.problem(format!("Inode {}: registro corrompido e ilegível; o Inode é liberado", ino));
+                    self.clear_inode(ino);
+                }
+            }
+
+            self.commit_if_large();
+        }
+
+        for index in 0..self.super_block.len() {
+            let ino = index as u64 + 1;
+
+            match self.super_block[index].as_ref().map(|inode| inode.attributes.ino) {
+                Some(recorded) => {
+                    if recorded != ino {
+                        check.problem(format!("Inode {} gravado com o número {}", ino, recorded));
+                        self.get_inode_as_mut(ino).unwrap().attributes.ino = ino;
+                    }
+
+                    if !self.inode_bitmap.is_set(index) {
+                        check.problem(format!("Inode {} ocupado, mas livre no mapa de Inodes", ino));
+                        self.inode_bitmap.set(index);
+                    }
+                },
+                None => if self.inode_bitmap.is_set(index) {
+                    check.problem(format!("Inode {} livre, mas ocupado no mapa de Inodes", ino));
+                    self.inode_bitmap.clear(index);
+                }
+            }
+
+            self.commit_if_large();
+        }
+
+        match self.super_block[0].as_ref().map(|root| (root.attributes.kind, root.parent)) {
+            Some((FileType::Directory, 1)) => (),
+            Some((FileType::Directory, parent)) => {
+                check.problem(format!("O \"..\" do root aponta para o Inode {}", parent));
+                self.get_inode_as_mut(1).unwrap().parent = 1;
+            },
+            Some(_) => return Err(io::Error::new(io::ErrorKind::InvalidData, "o root não é um diretório")),
+            None => {
+                check.problem("O root não existe: um root vazio é criado".to_string());
+                self.write_inode(directory_inode(1, 1, 0o755, unsafe { libc::getuid() }, unsafe { libc::getgid() }));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Percorre a árvore de diretórios a partir de `start`, que já deve estar entre os Inodes alcançados, conferindo
+    /// os blocos de cada Inode alcançado e as entradas de cada diretório.
+    fn check_tree(&mut self, check: &mut Check, start: u64) {
+        self.check_blocks(check, start);
+
+        let mut pending: VecDeque<u64> = VecDeque::new();
+        pending.push_back(start);
+
+        while let Some(dir) = pending.pop_front() {
+            if self.get_inode(dir).map(|inode| inode.attributes.kind) != Some(FileType::Directory) {
+                continue;
+            }
+
+            for entry in self.check_directory(check, dir) {
+                if entry.kind == FileType::Directory {
+                    // Diretórios já são marcados como alcançados por `check_directory`
+                    self.check_blocks(check, entry.ino);
+
+                    let parent = self.get_inode(entry.ino).unwrap().parent;
+                    if parent != dir {
+                        check.problem(format!("O \"..\" do diretório {} aponta para o Inode {} e não para {}", entry.ino, parent, dir));
+                        self.get_inode_as_mut(entry.ino).unwrap().parent = dir;
+                    }
+
+                    pending.push_back(entry.ino);
+                } else if check.visited.insert(entry.ino) {
+                    self.check_blocks(check, entry.ino);
+                }
+            }
+
+            self.commit_if_large();
+        }
+    }
+
+    /// Confere o índice e as entradas do diretório `dir` e retorna as entradas válidas, com o tipo corrigido. Se o
+    /// índice ou alguma entrada estiver errado, o diretório é reescrito depois só com as entradas válidas; as entradas
+    /// dos buckets ilegíveis são perdidas. Cada subdiretório é marcado como alcançado aqui, para que um segundo caminho
+    /// até ele seja descartado.
+    fn check_directory(&mut self, check: &mut Check, dir: u64) -> Vec<DirectoryEntry> {
+        let size = self.get_inode(dir).unwrap().attributes.size as usize;
+        let blocks = size / self.block_size;
+
+        let mut rebuild = false;
+        if !size.is_multiple_of(self.block_size) {
+            check.problem(format!("Diretório {} com tamanho inválido para o índice: {} bytes", dir, size));
+            rebuild = true;
+        }
+
+        // Buckets do diretório, com o intervalo de posições da tabela que aponta para cada um
+        let mut buckets: Vec<(usize, Option<(usize, usize)>)> = Vec::new();
+        let depth = if size == 0 { None } else { self.directory_depth(dir).ok().flatten() };
+        let table = depth.and_then(|depth| self.read_directory_table(dir, depth, 0..1 << depth).ok());
+
+        match (depth, table) {
+            (Some(depth), Some(table)) => {
+                let mut first = 0;
+                for slot in 1..=table.len() {
+                    if slot == table.len() || table[slot] != table[first] {
+                        buckets.push((table[first], Some((first, slot - first))));
+                        first = slot;
+                    }
+                }
+
+                let mut referenced: Vec<usize> = buckets.iter().map(|(logical, _)| *logical).collect();
+                referenced.sort_unstable();
+                if referenced.windows(2).any(|pair| pair[0] == pair[1]) {
+                    check.problem(format!("Índice do diretório {} corrompido: bucket apontado por posições separadas", dir));
+                    rebuild = true;
+                }
+
+                let unreferenced = (self.table_blocks(depth)..blocks).filter(|logical| referenced.binary_search(logical).is_err()).count();
+                if unreferenced > 0 {
+                    check.problem(format!("Diretório {} com {} blocos fora do índice", dir, unreferenced));
+                    rebuild = true;
+                }
+            },
+            _ if size == 0 => (),
+            (depth, _) => {
+                // Sem a tabela, as entradas são procuradas em todos os blocos que podem ser buckets
+                check.problem(format!("Índice do diretório {} corrompido", dir));
+                rebuild = true;
+                buckets.extend((depth.map_or(1, |depth| self.table_blocks(depth))..blocks).map(|logical| (logical, None)));
+            }
+        }
+
+        let mut entries: Vec<DirectoryEntry> = Vec::new();
+        let mut names: HashSet<Vec<u8>> = HashSet::new();
+
+        for (logical, slots) in buckets {
+            let bucket = match self.read_bucket(dir, logical) {
+                Ok(bucket) => bucket,
+                Err(_) if slots.is_none() => continue,
+                Err(_) => {
+                    check.problem(format!("Bucket {} do diretório {} corrompido: as suas entradas são perdidas", logical, dir));
+                    rebuild = true;
+                    continue;
+                }
+            };
+
+            // As posições de um bucket com profundidade `d` são um intervalo alinhado de `2^(profundidade - d)`
+            if let (Some(depth), Some((first, count))) = (depth, slots) {
+                if bucket.depth > depth || count != 1 << (depth - bucket.depth) || !first.is_multiple_of(count) {
+                    check.problem(format!("Bucket {} do diretório {} com a profundidade errada", logical, dir));
+                    rebuild = true;
+                }
+            }
+
+            let hashes: Vec<u64> = bucket.entries.iter().map(|entry| name_hash(&entry.name)).collect();
+            if hashes.windows(2).any(|pair| pair[0] > pair[1]) {
+                check.problem(format!("Bucket {} do diretório {} fora da ordem dos hashes", logical, dir));
+                rebuild = true;
+            }
+
+            for entry in bucket.entries {
+                let name = OsStr::from_bytes(&entry.name);
+                let target = self.entry_target(entry.ino);
+
+                let invalid = if !is_valid_name(&entry.name) {
+                    Some("tem um nome inválido".to_string())
+                } else if !names.insert(entry.name.clone()) {
+                    Some("é repetida".to_string())
+                } else {
+                    match target {
+                        None => Some(format!("aponta para o Inode {}, que não existe", entry.ino)),
+                        Some(FileType::Directory) if entry.ino == 1 || !check.visited.insert(entry.ino) => {
+                            Some(format!("aponta para o diretório {}, que já está em outro lugar da árvore", entry.ino))
+                        },
+                        Some(_) => None
+                    }
+                };
+
+                if let Some(reason) = invalid {
+                    check.problem(format!("Entrada {:?} do diretório {} {}", name, dir, reason));
+                    rebuild = true;
+                    continue;
+                }
+
+                let kind = target.unwrap();
+                if entry.kind != kind {
+                    check.problem(format!("Entrada {:?} do diretório {} com o tipo errado", name, dir));
+                    rebuild = true;
+                }
+
+                if let (Some(depth), Some((first, count))) = (depth, slots) {
+                    if !(first..first + count).contains(&slot_of(name_hash(&entry.name), depth)) {
+                        check.problem(format!("Entrada {:?} do diretório {} fora do bucket do seu nome", name, dir));
+                        rebuild = true;
+                    }
+                }
+
+                entries.push(DirectoryEntry { kind, ..entry });
+            }
+        }
+
+        if rebuild {
+            check.rebuild.push((dir, entries.clone()));
+        }
+
+        entries
+    }
+
+    /// Tipo do Inode para o qual uma entrada de diretório aponta, ou `None` se ele não existir.
+    fn entry_target(&self, ino: u64) -> Option<FileType> {
+        if ino == 0 || ino as usize > self.super_block.len() {
+            return None;
+        }
+
+        self.get_inode(ino).map(|inode| inode.attributes.kind)
+    }
+
+    /// Confere os blocos de memória do Inode `ino`: remove os ponteiros inválidos, libera os blocos após o fim do
+    /// arquivo e marca os blocos restantes como do Inode. Blocos que faltam antes do fim são buracos, lidos como
+    /// zeros. Blocos com conteúdo que já pertencem a outro Inode são copiados depois para um bloco novo.
+    fn check_blocks(&mut self, check: &mut Check, ino: u64) {
+        for invalid in self.invalid_pointers(check, ino) {
+            check.problem(format!("Inode {}: ponteiro para o bloco de memória {} {}", ino, invalid.block_index, invalid.reason));
+
+            match invalid.pointer {
+                Pointer::Reference(index) => self.get_inode_as_mut(ino).unwrap().references[index] = None,
+                Pointer::Slot(pointers, slot) => {
+                    if self.write_pointers(pointers, slot..slot + 1, None).is_err() {
+                        check.unrepaired(format!("Não foi possível alterar o bloco de ponteiros {}!", pointers));
+                    }
+                }
+            }
+
+            if let Some(logical) = invalid.shared {
+                check.copies.insert((ino, logical), invalid.block_index);
+            }
+        }
+
+        let (size, mut references) = {
+            let inode = self.get_inode(ino).unwrap();
+            (inode.attributes.size, inode.references)
+        };
+        let block_quantity = (size as usize).div_ceil(self.block_size);
+        let (logical_blocks, pointer_starts) = self.file_layout(&references);
+
+        if logical_blocks.iter().chain(pointer_starts.iter()).any(|logical| *logical >= block_quantity) {
+            check.problem(format!("Inode {}: blocos de memória após o fim do arquivo ({} bytes)", ino, size));
+
+            match self.free_file_blocks(&mut references, block_quantity) {
+                Ok(()) => self.get_inode_as_mut(ino).unwrap().references = references,
+                Err(_) => check.unrepaired(format!("Não foi possível liberar os blocos do Inode {}!", ino))
+            }
+        }
+
+        for block_index in self.file_blocks(self.get_inode(ino).unwrap()).unwrap_or_default() {
+            check.owners.insert(block_index, ino);
+        }
+    }
+
+    /// Ponteiros do Inode `ino` para blocos fora do disco, já usados por outro Inode ou repetidos no próprio Inode, e
+    /// para blocos de ponteiros ilegíveis.
+    fn invalid_pointers(&self, check: &Check, ino: u64) -> Vec<InvalidPointer> {
+        let references = self.get_inode(ino).unwrap().references;
+        let pointers = self.pointers_per_block();
+        let mut seen: HashSet<usize> = HashSet::new();
+        let mut invalid: Vec<InvalidPointer> = Vec::new();
+
+        // Confere o ponteiro `pointer` para o bloco `block_index`, que guarda o bloco lógico `logical` do arquivo se
+        // for um bloco com conteúdo
+        let mut validate = |invalid: &mut Vec<InvalidPointer>, pointer: Pointer, block_index: usize, logical: Option<usize>| {
+            let (reason, shared) = if block_index >= self.block_bitmap.capacity() {
+                ("fora do disco".to_string(), false)
+            } else if let Some(owner) = check.owners.get(&block_index) {
+                (format!("que pertence ao Inode {}", owner), true)
+            } else if !seen.insert(block_index) {
+                ("repetido".to_string(), true)
+            } else {
+                return true;
+            };
+
+            invalid.push(InvalidPointer { pointer, block_index, reason, shared: logical.filter(|_| shared) });
+            false
+        };
+
+        for (index, reference) in references.iter().enumerate() {
+            let block_index = match reference {
+                Some(block_index) => *block_index,
+                None => continue
+            };
+
+            let logical = if index < DIRECT_REFERENCES { Some(index) } else { None };
+            if !validate(&mut invalid, Pointer::Reference(index), block_index, logical) || logical.is_some() {
+                continue;
+            }
+
+            let slots = match self.read_slots(block_index) {
+                Some(slots) => slots,
+                None => {
+                    let reason = "ilegível".to_string();
+                    invalid.push(InvalidPointer { pointer: Pointer::Reference(index), block_index, reason, shared: None });
+                    continue;
+                }
+            };
+
+            for (slot, pointer) in slots.into_iter().enumerate() {
+                let pointer = match pointer {
+                    Some(pointer) => pointer,
+                    None => continue
+                };
+
+                if index == SINGLE_INDIRECT {
+                    validate(&mut invalid, Pointer::Slot(block_index, slot), pointer, Some(DIRECT_REFERENCES + slot));
+                    continue;
+                }
+
+                // As posições do bloco duplamente indireto apontam para outros blocos de ponteiros
+                if !validate(&mut invalid, Pointer::Slot(block_index, slot), pointer, None) {
+                    continue;
+                }
+
+                match self.read_slots(pointer) {
+                    Some(inner_slots) => {
+                        let first = DIRECT_REFERENCES + pointers + slot * pointers;
+                        for (inner, data) in inner_slots.into_iter().enumerate() {
+                            if let Some(data) = data {
+                                validate(&mut invalid, Pointer::Slot(pointer, inner), data, Some(first + inner));
+                            }
+                        }
+                    },
+                    None => {
+                        let reason = "ilegível".to_string();
+                        invalid.push(InvalidPointer { pointer: Pointer::Slot(block_index, slot), block_index: pointer, reason, shared: None });
+                    }
+                }
+            }
+        }
+
+        invalid
+    }
+
+    /// Blocos lógicos com conteúdo do arquivo com as references `references` e o primeiro bloco lógico coberto por
+    /// cada um dos seus blocos de ponteiros. Todos os blocos de ponteiros já devem ter sido conferidos.
+    fn file_layout(&self, references: &[Option<usize>; 128]) -> (Vec<usize>, Vec<usize>) {
+        let pointers = self.pointers_per_block();
+        let mut logical_blocks: Vec<usize> = (0..DIRECT_REFERENCES).filter(|index| references[*index].is_some()).collect();
+        let mut pointer_starts: Vec<usize> = Vec::new();
+
+        if let Some(single) = references[SINGLE_INDIRECT] {
+            pointer_starts.push(DIRECT_REFERENCES);
+            for (slot, pointer) in self.read_slots(single).unwrap_or_default().into_iter().enumerate() {
+                if pointer.is_some() {
+                    logical_blocks.push(DIRECT_REFERENCES + slot);
+                }
+            }
+        }
+
+        if let Some(double) = references[DOUBLE_INDIRECT] {
+            let first = DIRECT_REFERENCES + pointers;
+            pointer_starts.push(first);
+
+            for (outer, outer_pointers) in self.read_slots(double).unwrap_or_default().into_iter().enumerate() {
+                if let Some(outer_pointers) = outer_pointers {
+                    let start = first + outer * pointers;
+                    pointer_starts.push(start);
+
+                    for (inner, pointer) in self.read_slots(outer_pointers).unwrap_or_default().into_iter().enumerate() {
+                        if pointer.is_some() {
+                            logical_blocks.push(start + inner);
+                        }
+                    }
+                }
+            }
+        }
+
+        (logical_blocks, pointer_starts)
+    }
+
+    /// Percorre os Inodes que não foram alcançados a partir do root. Os que não são apontados por nenhum outro
+    /// diretório órfão vão para o lost+found junto com tudo o que é alcançável a partir deles; os arquivos vazios e os
+    /// removidos enquanto estavam abertos (sem nenhuma ligação) são liberados, como a montagem faria.
+    fn check_orphans(&mut self, check: &mut Check) {
+        let orphans: Vec<u64> = (1..=self.super_block.len() as u64)
+            .filter(|ino| self.get_inode(*ino).is_some() && !check.visited.contains(ino))
+            .collect();
+
+        // Inodes apontados por diretórios órfãos são alcançados ao percorrer esses diretórios, a não ser que os
+        // diretórios formem um ciclo
+        let referenced: HashSet<u64> = orphans.iter()
+            .filter(|ino| self.get_inode(**ino).unwrap().attributes.kind == FileType::Directory)
+            .flat_map(|dir| self.read_directory(*dir).unwrap_or_default())
+            .map(|entry| entry.ino)
+            .collect();
+
+        let (top, nested): (Vec<u64>, Vec<u64>) = orphans.into_iter().partition(|ino| !referenced.contains(ino));
+
+        for ino in top.into_iter().chain(nested) {
+            if !check.visited.insert(ino) {
+                continue;
+            }
+
+            let attributes = self.get_inode(ino).unwrap().attributes;
+            let reason = match attributes {
+                _ if attributes.kind == FileType::Directory => None,
+                _ if attributes.nlink == 0 => Some("removido enquanto estava aberto"),
+                _ if attributes.size == 0 => Some("vazio e sem nenhuma entrada"),
+                _ => None
+            };
+
+            if let Some(reason) = reason {
+                check.problem(format!("Inode {} {}: ele é liberado", ino, reason));
+                // Os ponteiros inválidos são descartados antes, para que só blocos do próprio Inode sejam liberados, e os
+                // blocos liberados deixam de ter dono
+                self.check_blocks(check, ino);
+                self.free_inode(ino);
+                check.owners.retain(|_, owner| *owner != ino);
+                check.copies.retain(|(owner, _), _| *owner != ino);
+                continue;
+            }
+
+            check.problem(format!("Inode {} não é alcançável a partir do root", ino));
+            check.lost.push(ino);
+            self.check_tree(check, ino);
+            self.commit_if_large();
+        }
+    }
+
+    /// Confere o mapa de blocos com os blocos de memória que pertencem a algum Inode.
+    fn check_block_bitmap(&mut self, check: &mut Check) {
+        let mut leaked: Vec<usize> = Vec::new();
+        let mut unmarked: Vec<usize> = Vec::new();
+
+        for block_index in 0..self.block_bitmap.capacity() {
+            match (self.block_bitmap.is_set(block_index), check.owners.contains_key(&block_index)) {
+                (true, false) => leaked.push(block_index),
+                (false, true) => unmarked.push(block_index),
+                _ => ()
+            }
+        }
+
+        if !leaked.is_empty() {
+            check.problem(format!("Blocos de memória ocupados sem pertencer a nenhum Inode: {}", ranges(&leaked)));
+            for block_index in leaked {
+                self.clear_memory_block(block_index);
+                self.commit_if_large();
+            }
+        }
+
+        if !unmarked.is_empty() {
+            check.problem(format!("Blocos de memória em uso, mas livres no mapa de blocos: {}", ranges(&unmarked)));
+            for block_index in unmarked {
+                self.block_bitmap.set(block_index);
+            }
+        }
+    }
+
+    /// Aloca um bloco novo para cada bloco lógico que era compartilhado com outro Inode, com uma cópia do conteúdo
+    /// dele. Sem espaço livre, o bloco lógico fica como um buraco e o seu conteúdo é perdido.
+    fn copy_shared_blocks(&mut self, check: &mut Check) {
+        let mut copies: Vec<((u64, usize), usize)> = mem::take(&mut check.copies).into_iter().collect();
+        copies.sort_unstable();
+
+        for ((ino, logical), source) in copies {
+            let (size, mut references) = {
+                let inode = self.get_inode(ino).unwrap();
+                (inode.attributes.size, inode.references)
+            };
+
+            // Os blocos após o fim do arquivo já foram liberados
+            if logical >= (size as usize).div_ceil(self.block_size) {
+                continue;
+            }
+
+            // O conteúdo é lido antes da alocação, que pode reaproveitar o bloco
+            let content = self.with_memory_block(source, |data| data.to_vec());
+            let copied = match self.missing_blocks(&references, logical..logical + 1) {
+                Ok(needed) if needed > self.free_memory_blocks() => Err(ENOSPC),
+                Ok(_) => self.allocate_file_block(&mut references, logical),
+                Err(e) => Err(e)
+            };
+            self.get_inode_as_mut(ino).unwrap().references = references;
+
+            match (copied, content) {
+                (Ok(block_index), Some(content)) => self.write_content_as_bytes(block_index, content.into_boxed_slice()),
+                (Ok(_), None) => check.unrepaired(format!(
+                    "Não foi possível ler o bloco de memória {}: o bloco lógico {} do Inode {} ficou zerado", source, logical, ino
+                )),
+                (Err(_), _) => check.unrepaired(format!(
+                    "Sem espaço para copiar o bloco lógico {} do Inode {}: o conteúdo dele foi perdido", logical, ino
+                ))
+            }
+
+            self.commit_if_large();
+        }
+    }
+
+    /// Reescreve os diretórios com entradas erradas somente com as entradas válidas, montando o índice do zero.
+    fn rebuild_directories(&mut self, check: &mut Check) {
+        for (dir, entries) in mem::take(&mut check.rebuild) {
+            let rebuilt = self.resize_content(dir, 0).and_then(|_| entries.iter().try_for_each(|entry| {
+                self.add_directory_entry(dir, OsStr::from_bytes(&entry.name), entry.ino, entry.kind)?;
+                self.commit_if_large();
+                Ok(())
+            }));
+
+            if let Err(e) = rebuilt {
+                check.unrepaired(format!("Não foi possível reescrever o diretório {}! Erro {}", dir, e));
+            }
+        }
+    }
+
+    /// Coloca os Inodes órfãos no lost+found, que é criado se ainda não existir, com o nome `#<ino>`.
+    fn adopt_orphans(&mut self, check: &mut Check) {
+        let lost = mem::take(&mut check.lost);
+        if lost.is_empty() {
+            return;
+        }
+
+        let lost_and_found = match self.find_directory_entry(1, OsStr::new(LOST_AND_FOUND)) {
+            Some(entry) if entry.kind == FileType::Directory => entry.ino,
+            Some(_) => {
+                check.unrepaired(format!("/{} não é um diretório! Os Inodes órfãos ficam onde estão", LOST_AND_FOUND));
+                return;
+            },
+            None => match self.create_lost_and_found() {
+                Ok(ino) => ino,
+                Err(e) => {
+                    check.unrepaired(format!("Não foi possível criar o /{}! Erro {}", LOST_AND_FOUND, e));
+                    return;
+                }
+            }
+        };
+
+        for ino in lost {
+            let kind = self.get_inode(ino).unwrap().attributes.kind;
+
+            let mut name = format!("#{}", ino);
+            let mut copy = 1;
+            while self.find_directory_entry(lost_and_found, OsStr::new(&name)).is_some() {
+                copy += 1;
+                name = format!("#{}.{}", ino, copy);
+            }
+
+            if let Err(e) = self.add_directory_entry(lost_and_found, OsStr::new(&name), ino, kind) {
+                check.unrepaired(format!("Não foi possível colocar o Inode {} no /{}! Erro {}", ino, LOST_AND_FOUND, e));
+                continue;
+            }
+
+            if kind == FileType::Directory {
+                self.get_inode_as_mut(ino).unwrap().parent = lost_and_found;
+                self.increment_nlink(lost_and_found);
+            }
+
+            self.commit_if_large();
+        }
+    }
+
+    /// Cria o /lost+found, com o mesmo dono do root e acessível somente por ele.
+    fn create_lost_and_found(&mut self) -> Result<u64, c_int> {
+        let ino = self.find_ino_available().ok_or(ENOSPC)?;
+        let (uid, gid) = {
+            let root = self.get_inode(1).unwrap();
+            (root.attributes.uid, root.attributes.gid)
+        };
+
+        println!("Criando o /{}...", LOST_AND_FOUND);
+        self.write_inode(directory_inode(ino, 1, 0o700, uid, gid));
+
+        if let Err(e) = self.add_directory_entry(1, OsStr::new(LOST_AND_FOUND), ino, FileType::Directory) {
+            self.clear_inode(ino);
+            return Err(e);
+        }
+
+        self.increment_nlink(1);
+        Ok(ino)
+    }
+
+    /// Confere o `nlink` e a quantidade de setores (`blocks`) de cada Inode com o disco já corrigido.
+    fn check_counts(&mut self, check: &mut Check) {
+        let links = self.expected_links();
+
+        for (index, nlink) in links.into_iter().enumerate() {
+            let ino = index as u64 + 1;
+            let (recorded_nlink, recorded_blocks, blocks) = match self.get_inode(ino) {
+                Some(inode) => {
+                    let blocks = self.file_blocks(inode).map_or(0, |blocks| blocks.len());
+                    (inode.attributes.nlink, inode.attributes.blocks, self.blocks_to_sectors(blocks))
+                },
+                None => continue
+            };
+
+            if recorded_nlink != nlink {
+                check.problem(format!("Inode {}: nlink {}, mas possui {} ligações", ino, recorded_nlink, nlink));
+                self.get_inode_as_mut(ino).unwrap().attributes.nlink = nlink;
+            }
+
+            if recorded_blocks != blocks {
+                check.problem(format!("Inode {}: {} setores registrados, mas ocupa {}", ino, recorded_blocks, blocks));
+                self.get_inode_as_mut(ino).unwrap().attributes.blocks = blocks;
+            }
+
+            self.commit_if_large();
+        }
+    }
+}
+
+/// Nome válido para uma entrada de diretório.
+fn is_valid_name(name: &[u8]) -> bool {
+    !name.is_empty() && name.len() <= NAME_MAX && name != b"." && name != b".." && !name.contains(&b'/') && !name.contains(&0)
+}
+
+/// Lista de índices em ordem crescente no formato `1-5, 8, 10-12`.
+pub fn ranges(indexes: &[usize]) -> String {
+    let mut ranges: Vec<String> = Vec::new();
+    let mut start = 0;
+
+    for position in 0..indexes.len() {
+        let last = position + 1 == indexes.len() || indexes[position + 1] != indexes[position] + 1;
+        if last {
+            ranges.push(if start == position {
+                indexes[start].to_string()
+            } else {
+                format!("{}-{}", indexes[start], indexes[position])
+            });
+            start = position + 1;
+        }
+    }
+
+    ranges.join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persistence::tests::{TempImage, create};
+
+    /// Verifica e corrige a imagem, gravando as correções. Uma segunda verificação não pode encontrar mais nada.
+    fn repair(image: &TempImage) -> CheckSummary {
+        let mut disk = Disk::load(&image.0, false).unwrap();
+        let summary = disk.check().unwrap();
+        disk.write_to_disk().unwrap();
+        drop(disk);
+
+        let mut disk = Disk::load(&image.0, true).unwrap();
+        let second = disk.check().unwrap();
+        assert_eq!((second.problems, second.unrepaired), (0, 0));
+
+        summary
+    }
+
+    #[test]
+    fn clean_image_has_no_problems() {
+        let image = TempImage::new();
+        let mut disk = image.disk(8 << 20);
+        let directory = create(&mut disk, 1, "docs", FileType::Directory);
+        let file = create(&mut disk, directory, "arquivo", FileType::RegularFile);
+        disk.write_content(file, 0, &[7; 3000]).unwrap();
+        disk.write_to_disk().unwrap();
+        drop(disk);
+
+        let summary = repair(&image);
+        assert_eq!((summary.problems, summary.unrepaired), (0, 0));
+    }
+
+    #[test]
+    fn removes_dangling_entry() {
+        let image = TempImage::new();
+        let mut disk = image.disk(8 << 20);
+        create(&mut disk, 1, "arquivo", FileType::RegularFile);
+        disk.add_directory_entry(1, OsStr::new("fantasma"), 40, FileType::RegularFile).unwrap();
+        disk.write_to_disk().unwrap();
+        drop(disk);
+
+        let summary = repair(&image);
+        assert_eq!((summary.problems, summary.unrepaired), (1, 0));
+
+        let disk = Disk::load(&image.0, true).unwrap();
+        assert!(disk.find_directory_entry(1, OsStr::new("fantasma")).is_none());
+        assert!(disk.find_directory_entry(1, OsStr::new("arquivo")).is_some());
+    }
+
+    #[test]
+    fn frees_leaked_block() {
+        let image = TempImage::new();
+        let mut disk = image.disk(8 << 20);
+        let free_before = disk.free_memory_blocks();
+        let leaked = disk.find_index_of_empty_memory_block().unwrap();
+        disk.write_content_as_bytes(leaked, Box::from(&b"sem dono"[..]));
+        disk.write_to_disk().unwrap();
+        drop(disk);
+
+        let summary = repair(&image);
+        assert_eq!((summary.problems, summary.unrepaired), (1, 0));
+
+        let disk = Disk::load(&image.0, true).unwrap();
+        assert!(!disk.bitmap(false).is_set(leaked));
+        assert_eq!(disk.free_memory_blocks(), free_before);
+    }
+
+    #[test]
+    fn moves_orphan_to_lost_and_found() {
+        let image = TempImage::new();
+        let mut disk = image.disk(8 << 20);
+        let orphan = disk.find_ino_available().unwrap();
+        let mut inode = directory_inode(orphan, 1, 0o644, 0, 0);
+        inode.attributes.kind = FileType::RegularFile;
+        inode.attributes.nlink = 1;
+        disk.write_inode(inode);
+        disk.write_content(orphan, 0, b"sem entrada").unwrap();
+        disk.write_to_disk().unwrap();
+        drop(disk);
+
+        let summary = repair(&image);
+        assert!(summary.problems > 0);
+        assert_eq!(summary.unrepaired, 0);
+
+        let disk = Disk::load(&image.0, true).unwrap();
+        let lost_and_found = disk.find_directory_entry(1, OsStr::new(LOST_AND_FOUND)).unwrap().ino;
+        let entry = disk.find_directory_entry(lost_and_found, OsStr::new(&format!("#{}", orphan))).unwrap();
+        assert_eq!(entry.ino, orphan);
+        assert_eq!(disk.get_inode(orphan).unwrap().attributes.nlink, 1);
+        assert_eq!(disk.read_content(orphan, 0, 100).unwrap(), b"sem entrada");
+    }
+
+    #[test]
+    fn rebuilds_directory_with_corrupted_index() {
+        let image = TempImage::new();
+        let mut disk = image.disk(8 << 20);
+        let file = create(&mut disk, 1, "arquivo", FileType::RegularFile);
+        for i in 0..100 {
+            disk.add_directory_entry(1, OsStr::new(&format!("link-{}", i)), file, FileType::RegularFile).unwrap();
+            disk.increment_nlink(file);
+        }
+
+        // A primeira posição da tabela passa a apontar para o próprio cabeçalho
+        disk.write_content(1, 8, &0u64.to_le_bytes()).unwrap();
+        assert!(disk.read_directory(1).is_none());
+        disk.write_to_disk().unwrap();
+        drop(disk);
+
+        let summary = repair(&image);
+        assert!(summary.problems > 0);
+        assert_eq!(summary.unrepaired, 0);
+
+        let disk = Disk::load(&image.0, true).unwrap();
+        assert_eq!(disk.read_directory(1).unwrap().len(), 101);
+        for i in 0..100 {
+            assert_eq!(disk.find_directory_entry(1, OsStr::new(&format!("link-{}", i))).unwrap().ino, file);
+        }
+    }
+
+    #[test]
+    fn frees_unlinked_orphan_instead_of_adopting_it() {
+        let image = TempImage::new();
+        let mut disk = image.disk(8 << 20);
+        let free_before = disk.free_memory_blocks();
+
+        // Arquivo removido enquanto estava aberto, que a montagem liberaria
+        let unlinked = disk.find_ino_available().unwrap();
+        let mut inode = directory_inode(unlinked, 1, 0o644, 0, 0);
+        inode.attributes.kind = FileType::RegularFile;
+        inode.attributes.nlink = 0;
+        disk.write_inode(inode);
+        disk.write_content(unlinked, 0, &[1; 3000]).unwrap();
+        disk.write_to_disk().unwrap();
+        drop(disk);
+
+        let summary = repair(&image);
+        assert_eq!((summary.problems, summary.unrepaired), (1, 0));
+
+        let disk = Disk::load(&image.0, true).unwrap();
+        assert!(disk.get_inode(unlinked).is_none());
+        assert!(disk.find_directory_entry(1, OsStr::new(LOST_AND_FOUND)).is_none());
+        assert_eq!(disk.free_memory_blocks(), free_before);
+    }
+}
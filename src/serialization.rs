@@ -46,4 +46,68 @@ pub struct FileAttrDef {
     pub gid: u32,
     pub rdev: u32,
     pub flags: u32,
-}
\ No newline at end of file
+}
+
+/// Serializa um `Vec<u8>` de uma vez só, em vez de byte a byte. O formato gravado pelo bincode é o mesmo de um
+/// `Vec<u8>` comum: o tamanho seguido dos bytes.
+pub mod bytes {
+    use std::fmt;
+    use serde::{Serializer, Deserializer};
+    use serde::de::{Visitor, SeqAccess, Error};
+
+    pub fn serialize<T: AsRef<[u8]>, S: Serializer>(bytes: &T, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(bytes.as_ref())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        deserializer.deserialize_byte_buf(BytesVisitor)
+    }
+
+    struct BytesVisitor;
+
+    impl<'de> Visitor<'de> for BytesVisitor {
+        type Value = Vec<u8>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("uma sequência de bytes")
+        }
+
+        fn visit_bytes<E: Error>(self, bytes: &[u8]) -> Result<Vec<u8>, E> {
+            Ok(bytes.to_vec())
+        }
+
+        fn visit_byte_buf<E: Error>(self, bytes: Vec<u8>) -> Result<Vec<u8>, E> {
+            Ok(bytes)
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<u8>, A::Error> {
+            let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+            while let Some(byte) = seq.next_element()? {
+                bytes.push(byte);
+            }
+            Ok(bytes)
+        }
+    }
+}
+
+
+/// Serializa os blocos de memória de uma transação com o conteúdo de cada um de uma vez só. O formato gravado pelo
+/// bincode é o mesmo de um `Vec<(usize, Vec<u8>)>` comum.
+pub mod blocks {
+    use serde::{Serialize, Deserialize, Serializer, Deserializer};
+
+    #[derive(Serialize)]
+    struct BlockRef<'a>(usize, #[serde(with = "super::bytes")] &'a [u8]);
+
+    #[derive(Deserialize)]
+    struct Block(usize, #[serde(with = "super::bytes")] Vec<u8>);
+
+    pub fn serialize<S: Serializer>(blocks: &[(usize, Vec<u8>)], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(blocks.iter().map(|(block_index, data)| BlockRef(*block_index, data)))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<(usize, Vec<u8>)>, D::Error> {
+        let blocks = Vec::<Block>::deserialize(deserializer)?;
+        Ok(blocks.into_iter().map(|Block(block_index, data)| (block_index, data)).collect())
+    }
+}
@@ -0,0 +1,53 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::str::FromStr;
+use serde::{Serialize, Deserialize};
+
+/// Identificador único de um disco, gravado no superbloco e exibido no formato usual
+/// `xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx`.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct Uuid(pub [u8; 16]);
+
+impl Uuid {
+    /// Gera um UUID aleatório (versão 4, RFC 4122) a partir de /dev/urandom.
+    pub fn random() -> io::Result<Uuid> {
+        let mut bytes = [0u8; 16];
+        File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+
+        bytes[6] = (bytes[6] & 0x0f) | 0x40;
+        bytes[8] = (bytes[8] & 0x3f) | 0x80;
+        Ok(Uuid(bytes))
+    }
+}
+
+impl fmt::Display for Uuid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (index, byte) in self.0.iter().enumerate() {
+            if index == 4 || index == 6 || index == 8 || index == 10 {
+                write!(f, "-")?;
+            }
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Uuid {
+    type Err = ();
+
+    /// Lê um UUID com 32 dígitos hexadecimais, com ou sem os hífens.
+    fn from_str(text: &str) -> Result<Uuid, ()> {
+        let digits: Vec<u8> = text.bytes().filter(|byte| *byte != b'-').collect();
+        if digits.len() != 32 || text.len() - digits.len() > 4 || !digits.iter().all(u8::is_ascii_hexdigit) {
+            return Err(());
+        }
+
+        let digit = |byte: u8| (byte as char).to_digit(16).unwrap() as u8;
+        let mut bytes = [0u8; 16];
+        for (byte, pair) in bytes.iter_mut().zip(digits.chunks(2)) {
+            *byte = digit(pair[0]) << 4 | digit(pair[1]);
+        }
+        Ok(Uuid(bytes))
+    }
+}